@@ -0,0 +1,20 @@
+-- Day 12 games and the moves played in them
+CREATE TABLE IF NOT EXISTS games (
+    id UUID PRIMARY KEY,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'ongoing',
+    result TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS games_status_idx ON games (status, created_at);
+
+CREATE TABLE IF NOT EXISTS game_moves (
+    game_id UUID NOT NULL REFERENCES games (id) ON DELETE CASCADE,
+    number INT NOT NULL,
+    team TEXT NOT NULL,
+    column_number INT NOT NULL,
+    PRIMARY KEY (game_id, number)
+);
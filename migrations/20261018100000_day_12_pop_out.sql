@@ -0,0 +1,4 @@
+-- Rule variant per game, and whether each move dropped or popped an item
+ALTER TABLE games ADD COLUMN IF NOT EXISTS variant TEXT NOT NULL DEFAULT 'classic';
+
+ALTER TABLE game_moves ADD COLUMN IF NOT EXISTS kind TEXT NOT NULL DEFAULT 'drop';
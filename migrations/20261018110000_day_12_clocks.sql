@@ -0,0 +1,6 @@
+-- Optional time control per game, and when each move was made so clocks survive a restart
+ALTER TABLE games ADD COLUMN IF NOT EXISTS clock TEXT;
+
+ALTER TABLE games ADD COLUMN IF NOT EXISTS clock_seconds INT;
+
+ALTER TABLE game_moves ADD COLUMN IF NOT EXISTS played_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
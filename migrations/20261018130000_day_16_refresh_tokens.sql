@@ -0,0 +1,11 @@
+-- Refresh tokens issued alongside gift cookies. Tokens rotated from the same `wrap` share a family,
+-- which is revoked as a whole (through revoked_tokens) when a used refresh token shows up again.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    jti UUID PRIMARY KEY,
+    family UUID NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_expires_at_idx ON refresh_tokens (expires_at);
@@ -0,0 +1,14 @@
+-- Every revision a quote has been through, so edits can be listed and reverted.
+-- Quotes that already exist start their history at their current version.
+CREATE TABLE IF NOT EXISTS quote_versions (
+    quote_id UUID NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    version INT NOT NULL,
+    author TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (quote_id, version)
+);
+
+INSERT INTO quote_versions (quote_id, version, author, quote)
+SELECT id, version, author, quote FROM quotes
+ON CONFLICT DO NOTHING;
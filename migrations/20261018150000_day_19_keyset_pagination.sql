@@ -0,0 +1,4 @@
+-- Quotes are now paged by signed (created_at, id) cursors rather than stored page tokens.
+DROP TABLE IF EXISTS quotes_pagination;
+
+CREATE INDEX IF NOT EXISTS quotes_created_at_id_idx ON quotes (created_at, id);
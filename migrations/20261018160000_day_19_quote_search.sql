@@ -0,0 +1,6 @@
+-- Full-text search over quotes, with matches on the author ranked above matches in the quote.
+ALTER TABLE quotes ADD COLUMN IF NOT EXISTS search TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('english', author), 'A') || setweight(to_tsvector('english', quote), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS quotes_search_idx ON quotes USING GIN (search);
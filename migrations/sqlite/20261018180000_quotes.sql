@@ -0,0 +1,43 @@
+-- The day 19 quote book for running against SQLite, mirroring the Postgres tables.
+-- Timestamps are written by the server, and quotes_fts stands in for the tsvector column.
+-- seq aliases the rowid so that VACUUM can't renumber it out from under quotes_fts.
+CREATE TABLE IF NOT EXISTS quotes (
+    seq INTEGER PRIMARY KEY,
+    id BLOB UNIQUE NOT NULL,
+    author TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    version INTEGER NOT NULL DEFAULT 1,
+    deleted_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS quotes_created_at_id_idx ON quotes (created_at, id);
+CREATE INDEX IF NOT EXISTS quotes_deleted_at_idx ON quotes (deleted_at) WHERE deleted_at IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS quote_versions (
+    quote_id BLOB NOT NULL REFERENCES quotes (id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    quote TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    PRIMARY KEY (quote_id, version)
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS quotes_fts USING fts5(
+    author, quote, content = 'quotes', content_rowid = 'seq', tokenize = 'porter'
+);
+
+CREATE TRIGGER IF NOT EXISTS quotes_fts_insert AFTER INSERT ON quotes BEGIN
+    INSERT INTO quotes_fts (rowid, author, quote) VALUES (new.seq, new.author, new.quote);
+END;
+
+CREATE TRIGGER IF NOT EXISTS quotes_fts_delete AFTER DELETE ON quotes BEGIN
+    INSERT INTO quotes_fts (quotes_fts, rowid, author, quote)
+    VALUES ('delete', old.seq, old.author, old.quote);
+END;
+
+CREATE TRIGGER IF NOT EXISTS quotes_fts_update AFTER UPDATE OF author, quote ON quotes BEGIN
+    INSERT INTO quotes_fts (quotes_fts, rowid, author, quote)
+    VALUES ('delete', old.seq, old.author, old.quote);
+    INSERT INTO quotes_fts (rowid, author, quote) VALUES (new.seq, new.author, new.quote);
+END;
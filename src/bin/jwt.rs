@@ -0,0 +1,184 @@
+//! Wrap and decode day 16 gift tokens from the command line, with the same keys and trusted
+//! issuers the server is configured with.
+//!
+//! ```text
+//! jwt wrap [--secrets FILE] [--lifetime SECONDS] [JSON]
+//! jwt wrap [--secrets FILE] [--lifetime SECONDS] --role ROLE... [--subject NAME]
+//! jwt decode [--secrets FILE] [TOKEN]
+//! ```
+//!
+//! Settings come from `--secrets` (a Shuttle `Secrets.toml`) and then the environment.
+//! The JSON or token is read from stdin when it isn't given.
+//! With `--role`, `wrap` issues a token carrying the roles instead of a gift, such as
+//! `--role admin` for the routes that reset state.
+
+use std::{io::Read, path::PathBuf, process::ExitCode};
+
+use jsonwebtoken::{Header, TokenData};
+use serde_json::{json, Value};
+use shuttlings_cch24::{config::Settings, sixteen::GiftCodec};
+
+const USAGE: &str = "Usage:
+    jwt wrap [--secrets FILE] [--lifetime SECONDS] [JSON]
+    jwt wrap [--secrets FILE] [--lifetime SECONDS] --role ROLE... [--subject NAME]
+    jwt decode [--secrets FILE] [TOKEN]";
+
+const DEFAULT_LIFETIME_SECONDS: i64 = 600;
+
+/// Subject of role tokens unless `--subject` says otherwise
+const DEFAULT_SUBJECT: &str = "jwt";
+
+enum Command {
+    Wrap,
+    Decode,
+}
+
+struct Args {
+    command: Command,
+    secrets: Option<PathBuf>,
+    lifetime: i64,
+    roles: Vec<String>,
+    subject: Option<String>,
+    input: Option<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let command = match args.next().as_deref() {
+        Some("wrap") => Command::Wrap,
+        Some("decode") => Command::Decode,
+        Some(other) => return Err(format!("Unknown command `{other}`")),
+        None => return Err("Missing command".to_string()),
+    };
+    let mut parsed = Args {
+        command,
+        secrets: None,
+        lifetime: DEFAULT_LIFETIME_SECONDS,
+        roles: Vec::new(),
+        subject: None,
+        input: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--secrets" => {
+                let path = args.next().ok_or("--secrets needs a file")?;
+                parsed.secrets = Some(path.into());
+            }
+            "--lifetime" if matches!(parsed.command, Command::Wrap) => {
+                let seconds = args.next().ok_or("--lifetime needs a number of seconds")?;
+                parsed.lifetime = match seconds.parse() {
+                    Ok(seconds) if seconds > 0 => seconds,
+                    _ => return Err(format!("Invalid lifetime `{seconds}`")),
+                };
+            }
+            "--role" if matches!(parsed.command, Command::Wrap) => {
+                parsed.roles.push(args.next().ok_or("--role needs a role")?);
+            }
+            "--subject" if matches!(parsed.command, Command::Wrap) => {
+                parsed.subject = Some(args.next().ok_or("--subject needs a name")?);
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown option `{flag}`")),
+            _ if parsed.input.is_some() => return Err(format!("Unexpected argument `{arg}`")),
+            _ => parsed.input = Some(arg),
+        }
+    }
+    if parsed.roles.is_empty() && parsed.subject.is_some() {
+        return Err("--subject only applies along with --role".to_string());
+    }
+    if !parsed.roles.is_empty() && parsed.input.is_some() {
+        return Err("A token with --role doesn't take JSON contents".to_string());
+    }
+    Ok(parsed)
+}
+
+fn read_input(input: Option<String>) -> Result<String, String> {
+    let input = match input {
+        Some(input) => input,
+        None => {
+            let mut input = String::new();
+            std::io::stdin()
+                .read_to_string(&mut input)
+                .map_err(|e| format!("Couldn't read stdin: {e}"))?;
+            input
+        }
+    };
+    Ok(input.trim().to_string())
+}
+
+fn print_token(source: &str, header: &Header, claims: &Value) {
+    let output = json!({ "source": source, "header": header, "claims": claims });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let settings = match &args.secrets {
+        Some(path) => Settings::from_secrets_file(path).map_err(|e| e.to_string())?,
+        None => Settings::default(),
+    };
+    if settings.get("GIFT_SIGNING_KEYS").is_none()
+        && settings.get("GIFT_SIGNING_KEY_FILES").is_none()
+    {
+        if !args.roles.is_empty() {
+            return Err(
+                "Role tokens need the server's signing keys, but none are configured".to_string(),
+            );
+        }
+        eprintln!("No signing keys are configured, so gifts use a key that only lasts this run");
+    }
+    let codec = GiftCodec::from_settings(&settings).map_err(|e| e.to_string())?;
+    if !args.roles.is_empty() {
+        let subject = args.subject.as_deref().unwrap_or(DEFAULT_SUBJECT);
+        let token = codec
+            .grant(subject, &args.roles, args.lifetime)
+            .map_err(|e| e.to_string())?;
+        println!("{token}");
+        return Ok(());
+    }
+    let input = read_input(args.input)?;
+
+    match args.command {
+        Command::Wrap => {
+            let contents: Value =
+                serde_json::from_str(&input).map_err(|e| format!("Invalid JSON: {e}"))?;
+            let token = codec
+                .wrap(contents, args.lifetime)
+                .map_err(|e| e.to_string())?;
+            println!("{token}");
+        }
+        Command::Decode => {
+            let gift_error = match codec.open::<Value>(&input) {
+                Ok(TokenData { header, claims }) => {
+                    print_token("gift", &header, &claims);
+                    return Ok(());
+                }
+                Err(e) => e,
+            };
+            match codec.decode_trusted::<Value>(&input) {
+                Ok(TokenData { header, claims }) => print_token("trusted_issuer", &header, &claims),
+                Err(e) => {
+                    return Err(format!(
+                        "Not a valid gift ({gift_error}) or trusted token ({}: {e})",
+                        e.class()
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
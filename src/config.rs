@@ -0,0 +1,74 @@
+use std::{collections::BTreeMap, path::Path, str::FromStr};
+
+use shuttle_runtime::SecretStore;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{name} can't be set to `{value}`")]
+    Invalid { name: String, value: String },
+    #[error("{0} must be set")]
+    Missing(String),
+    #[error("Couldn't read {path}: {message}")]
+    Unreadable { path: String, message: String },
+}
+
+/// Settings read from Shuttle Secrets, falling back to environment variables.
+/// Deliberately not `Debug`, since most of them are secrets.
+#[derive(Clone, Default)]
+pub struct Settings {
+    values: BTreeMap<String, String>,
+}
+
+impl Settings {
+    pub fn from_secrets(secrets: SecretStore) -> Self {
+        Self {
+            values: secrets.into_iter().collect(),
+        }
+    }
+
+    /// Settings from a `Secrets.toml` file in the format Shuttle uses, for running outside of it
+    pub fn from_secrets_file(path: &Path) -> Result<Self, ConfigError> {
+        let unreadable = |message: String| ConfigError::Unreadable {
+            path: path.display().to_string(),
+            message,
+        };
+        let text = std::fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+        let values = toml::from_str(&text).map_err(|e| unreadable(e.to_string()))?;
+        Ok(Self { values })
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// A comma separated setting split into its trimmed, non-empty entries
+    pub fn list(&self, name: &str) -> Vec<String> {
+        self.get(name)
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// A setting parsed into `T`, failing rather than falling back when it's malformed
+    pub fn parse<T: FromStr>(&self, name: &str) -> Result<Option<T>, ConfigError> {
+        self.get(name)
+            .map(|value| {
+                value.parse().map_err(|_| ConfigError::Invalid {
+                    name: name.to_string(),
+                    value,
+                })
+            })
+            .transpose()
+    }
+}
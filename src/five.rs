@@ -108,10 +108,10 @@ pub(super) async fn manifest(headers: HeaderMap, body: String) -> Response {
             .body(response.into())
             .unwrap()
     } else {
-        return Response::builder()
+        Response::builder()
             .status(StatusCode::NO_CONTENT)
             .body("".into())
-            .unwrap();
+            .unwrap()
     }
 }
 
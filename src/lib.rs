@@ -0,0 +1,4 @@
+//! Configuration and the day 16 gift token code, shared by the server and the `jwt` tool.
+
+pub mod config;
+pub mod sixteen;
@@ -2,22 +2,40 @@ mod five;
 mod minus_one;
 mod nine;
 mod nineteen;
-mod sixteen;
 mod twelve;
 mod twenty_three;
 mod two;
 
 use axum::{
+    middleware::from_fn_with_state,
     routing::{delete, get, post, put},
     Router,
 };
+use shuttlings_cch24::{config, sixteen};
 
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(
+    #[shuttle_shared_db::Postgres] pool: sqlx::PgPool,
+    #[shuttle_runtime::Secrets] secrets: shuttle_runtime::SecretStore,
+) -> shuttle_axum::ShuttleAxum {
     sqlx::migrate!()
         .run(&pool)
         .await
         .expect("Failed to run migrations");
+    let settings = config::Settings::from_secrets(secrets);
+    let gifts = sixteen::GiftState::from_settings(&settings, pool.clone())
+        .expect("Failed to configure gift tokens");
+    gifts.spawn_purge();
+    let quote_store = nineteen::open_store(&settings, pool.clone())
+        .await
+        .expect("Failed to open the quote store");
+    let quotes = nineteen::QuoteState::from_settings(&settings, quote_store)
+        .expect("Failed to configure quotes");
+    quotes.spawn_purge();
+    let games = twelve::AppState::restore(twelve::GameStore::new(pool.clone())).await;
+    twelve::AppState::spawn_flag_watch(&games);
+    let admin = from_fn_with_state(gifts.clone(), sixteen::require_admin);
+    let authenticated = from_fn_with_state(gifts.clone(), sixteen::authenticate);
 
     let router = Router::new()
         .route("/", get(minus_one::hello_bird))
@@ -28,23 +46,45 @@ async fn main(#[shuttle_shared_db::Postgres] pool: sqlx::PgPool) -> shuttle_axum
         .route("/2/v6/key", get(two::v6_key))
         .route("/5/manifest", post(five::manifest))
         .route("/9/milk", post(nine::milk))
-        .route("/9/refill", post(nine::refill))
+        .route("/9/refill", post(nine::refill).layer(admin.clone()))
         .with_state(nine::MilkState::construct())
+        .route("/12/play", get(twelve::play_page))
         .route("/12/board", get(twelve::board_state))
-        .route("/12/reset", post(twelve::reset_board))
+        // Anyone may start a finished game over under the same rules;
+        // abandoning one or changing its variant or clock takes an admin
+        .route(
+            "/12/reset",
+            post(twelve::reset_board).layer(authenticated.clone()),
+        )
         .route("/12/place/:team/:column", post(twelve::place))
+        .route("/12/pop/:team/:column", post(twelve::pop))
         .route("/12/random-board", get(twelve::random_board))
-        .with_state(twelve::AppState::construct())
+        .route("/12/ws", get(twelve::watch).layer(authenticated))
+        .route("/12/games", get(twelve::games))
+        .route("/12/tournament", post(twelve::tournament))
+        .with_state(games)
         .route("/16/wrap", post(sixteen::wrap))
         .route("/16/unwrap", get(sixteen::unwrap))
         .route("/16/decode", post(sixteen::decode_token))
-        .route("/19/reset", post(nineteen::reset))
+        .route("/16/revoke", post(sixteen::revoke))
+        .route("/16/refresh", post(sixteen::refresh))
+        .route(
+            "/16/introspect",
+            post(sixteen::introspect).layer(admin.clone()),
+        )
+        .route("/.well-known/jwks.json", get(sixteen::jwks))
+        .with_state(gifts)
+        .route("/19/reset", post(nineteen::reset).layer(admin))
         .route("/19/cite/:id", get(nineteen::cite))
         .route("/19/remove/:id", delete(nineteen::remove))
+        .route("/19/restore/:id", post(nineteen::restore))
         .route("/19/undo/:id", put(nineteen::undo))
+        .route("/19/history/:id", get(nineteen::history))
+        .route("/19/revert/:id/:version", post(nineteen::revert))
         .route("/19/draft", post(nineteen::draft))
         .route("/19/list", get(nineteen::list))
-        .with_state(nineteen::QuoteState { pool })
+        .route("/19/search", get(nineteen::search))
+        .with_state(quotes)
         .route("/assets/23.html", get(twenty_three::html))
         .route("/23/star", get(twenty_three::star))
         .route("/23/present/:color", get(twenty_three::present))
@@ -117,7 +117,7 @@ fn bad_request() -> Response<String> {
 
 fn conversion(body_text: &str) -> Option<ConversionRequest> {
     info!("{body_text}");
-    let Ok(conversion) = serde_json::from_str::<ConversionRequest>(&body_text) else {
+    let Ok(conversion) = serde_json::from_str::<ConversionRequest>(body_text) else {
         return None;
     };
     const LITERS_TO_GALLONS: f32 = 0.264172;
@@ -132,12 +132,12 @@ fn conversion(body_text: &str) -> Option<ConversionRequest> {
         return None;
     }
     if let Some(liters) = conversion.liters {
-        return Some(ConversionRequest {
+        Some(ConversionRequest {
             liters: None,
             gallons: Some(liters * LITERS_TO_GALLONS),
             litres: None,
             pints: None,
-        });
+        })
     } else if let Some(gallons) = conversion.gallons {
         Some(ConversionRequest {
             liters: Some(gallons * GALLONS_TO_LITERS),
@@ -145,13 +145,6 @@ fn conversion(body_text: &str) -> Option<ConversionRequest> {
             litres: None,
             pints: None,
         })
-    } else if let Some(litres) = conversion.liters {
-        Some(ConversionRequest {
-            liters: None,
-            gallons: Some(litres * LITERS_TO_GALLONS),
-            litres: None,
-            pints: None,
-        })
     } else if let Some(pints) = conversion.pints {
         Some(ConversionRequest {
             liters: None,
@@ -159,14 +152,12 @@ fn conversion(body_text: &str) -> Option<ConversionRequest> {
             litres: Some(pints * PINTS_TO_LITRES),
             pints: None,
         })
-    } else if let Some(litres) = conversion.litres {
-        Some(ConversionRequest {
+    } else {
+        conversion.litres.map(|litres| ConversionRequest {
             liters: None,
             gallons: None,
             litres: None,
             pints: Some(litres * LITRES_TO_PINTS),
         })
-    } else {
-        None
     }
 }
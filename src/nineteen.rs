@@ -1,18 +1,45 @@
 use axum::{
     body::Body,
     extract::{Json, Path, Query, State},
-    http::{Response, StatusCode},
+    http::{
+        header::{ETAG, IF_MATCH},
+        HeaderMap, HeaderValue, Response, StatusCode,
+    },
     response::IntoResponse,
 };
-use rand::{
-    distributions::{Alphanumeric, DistString},
-    thread_rng,
-};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
-use tracing::info;
+use std::{sync::Arc, time::Duration};
+use thiserror::Error;
+use tracing::{error, info};
 use uuid::Uuid;
 
+use crate::config::{ConfigError, Settings};
+
+mod cursor;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod store;
+
+use cursor::{Cursor, CursorSigner};
+use memory::MemoryQuoteStore;
+pub(super) use store::QuoteStore;
+use store::{PgQuoteStore, QuoteStoreError};
+
+const DEFAULT_PAGE_SIZE: i64 = 3;
+/// The largest `QUOTE_PAGE_SIZE` allowed, keeping pages cheap to fetch
+const MAX_PAGE_SIZE: i64 = 100;
+
+/// How long removed quotes can still be restored, unless `QUOTE_RETENTION_SECONDS` says otherwise
+const DEFAULT_RETENTION_SECONDS: i64 = 30 * 24 * 3600;
+/// The longest `QUOTE_RETENTION_SECONDS` allowed, well within what `chrono::Duration` can hold
+const MAX_RETENTION_SECONDS: i64 = 10 * 365 * 24 * 3600;
+
+/// How often removed quotes past their retention are purged,
+/// unless `QUOTE_PURGE_SECONDS` says otherwise
+const DEFAULT_PURGE_SECONDS: u64 = 3600;
+
 fn default_id() -> Uuid {
     Uuid::new_v4()
 }
@@ -25,7 +52,7 @@ fn default_version() -> i32 {
     1
 }
 
-#[derive(Debug, Deserialize, FromRow, Serialize)]
+#[derive(Clone, Debug, Deserialize, FromRow, Serialize)]
 pub(super) struct Quote {
     #[serde(default = "default_id")]
     id: Uuid,
@@ -37,11 +64,19 @@ pub(super) struct Quote {
     version: i32,
 }
 
-#[derive(Debug, FromRow)]
-pub struct PageToken {
-    #[allow(dead_code)]
-    id: String,
-    page: i32,
+/// A revision of a quote, as recorded in `quote_versions`
+#[derive(Clone, Debug, FromRow, Serialize)]
+pub(super) struct QuoteVersion {
+    version: i32,
+    author: String,
+    quote: String,
+    recorded_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct QuoteHistory {
+    id: Uuid,
+    versions: Vec<QuoteVersion>,
 }
 
 #[derive(Debug, Serialize)]
@@ -51,9 +86,130 @@ pub(super) struct QuoteList {
     quotes: Vec<Quote>,
 }
 
-#[derive(Clone, Debug)]
+/// A quote matching a search, with the matching words in the quote highlighted in `snippet`
+#[derive(Debug, FromRow, Serialize)]
+pub(super) struct SearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    quote: Quote,
+    rank: f64,
+    snippet: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct SearchResults {
+    next_token: Option<String>,
+    page: i32,
+    quotes: Vec<SearchHit>,
+}
+
+#[derive(Debug, Error)]
+pub(super) enum StoreError {
+    #[cfg(not(feature = "sqlite"))]
+    #[error("{0} needs the server to be built with the `sqlite` feature")]
+    SqliteDisabled(String),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Migration(#[from] sqlx::migrate::MigrateError),
+}
+
+/// The quote store to use: in memory when `QUOTE_STORE` is `memory`, SQLite when
+/// `DATABASE_URL` is a `sqlite:` URL, and otherwise the shared Postgres database.
+/// Only the quote book moves: the other days keep using Postgres either way
+pub(super) async fn open_store(
+    settings: &Settings,
+    pool: PgPool,
+) -> Result<Arc<dyn QuoteStore>, StoreError> {
+    if settings.get("QUOTE_STORE").as_deref() == Some("memory") {
+        info!("Keeping quotes in memory");
+        return Ok(Arc::new(MemoryQuoteStore::default()));
+    }
+    match settings.get("DATABASE_URL") {
+        #[cfg(feature = "sqlite")]
+        Some(url) if url.starts_with("sqlite:") => {
+            info!("Keeping quotes in SQLite");
+            Ok(Arc::new(sqlite::SqliteQuoteStore::connect(&url).await?))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some(url) if url.starts_with("sqlite:") => Err(StoreError::SqliteDisabled(url)),
+        _ => Ok(Arc::new(PgQuoteStore::new(pool))),
+    }
+}
+
+#[derive(Clone)]
 pub(super) struct QuoteState {
-    pub(crate) pool: PgPool,
+    store: Arc<dyn QuoteStore>,
+    cursors: CursorSigner,
+    /// Quotes per page of `/19/list`, set with `QUOTE_PAGE_SIZE`
+    page_size: i64,
+    /// How long removed quotes are kept, set with `QUOTE_RETENTION_SECONDS`
+    retention_seconds: i64,
+    /// How often removed quotes are purged, set with `QUOTE_PURGE_SECONDS`
+    purge_period: Duration,
+}
+
+impl QuoteState {
+    pub(super) fn from_settings(
+        settings: &Settings,
+        store: Arc<dyn QuoteStore>,
+    ) -> Result<Self, ConfigError> {
+        let page_size = settings
+            .parse("QUOTE_PAGE_SIZE")?
+            .unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&page_size) {
+            return Err(ConfigError::Invalid {
+                name: "QUOTE_PAGE_SIZE".to_string(),
+                value: page_size.to_string(),
+            });
+        }
+        let retention_seconds = settings
+            .parse("QUOTE_RETENTION_SECONDS")?
+            .unwrap_or(DEFAULT_RETENTION_SECONDS);
+        if !(0..=MAX_RETENTION_SECONDS).contains(&retention_seconds) {
+            return Err(ConfigError::Invalid {
+                name: "QUOTE_RETENTION_SECONDS".to_string(),
+                value: retention_seconds.to_string(),
+            });
+        }
+        let purge_seconds = settings
+            .parse("QUOTE_PURGE_SECONDS")?
+            .unwrap_or(DEFAULT_PURGE_SECONDS);
+        if purge_seconds == 0 {
+            return Err(ConfigError::Invalid {
+                name: "QUOTE_PURGE_SECONDS".to_string(),
+                value: purge_seconds.to_string(),
+            });
+        }
+        Ok(Self {
+            store,
+            cursors: CursorSigner::from_settings(settings),
+            page_size,
+            retention_seconds,
+            purge_period: Duration::from_secs(purge_seconds),
+        })
+    }
+
+    /// Permanently delete quotes removed more than `QUOTE_RETENTION_SECONDS` ago,
+    /// every `QUOTE_PURGE_SECONDS` for as long as the server runs
+    pub(super) fn spawn_purge(&self) {
+        let (store, retention_seconds, period) = (
+            self.store.clone(),
+            self.retention_seconds,
+            self.purge_period,
+        );
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                match store.purge_removed(retention_seconds).await {
+                    Ok(0) => (),
+                    Ok(purged) => info!("Purged {purged} removed quotes"),
+                    Err(e) => error!("Failed to purge removed quotes: {e}"),
+                }
+            }
+        });
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,33 +217,90 @@ pub(super) struct ListQuery {
     pub(crate) token: Option<String>,
 }
 
-async fn get_quote(pool: &PgPool, id: Uuid) -> Option<Quote> {
-    let quote = sqlx::query_as::<_, Quote>("SELECT * FROM quotes WHERE id = $1")
-        .bind(id)
-        .fetch_optional(pool)
-        .await
-        .unwrap();
-    quote
+#[derive(Debug, Deserialize)]
+pub(super) struct SearchQuery {
+    q: String,
+    author: Option<String>,
+    token: Option<String>,
+}
+
+/// A page token that's forged, malformed or from the other kind of listing
+struct InvalidPageToken;
+
+impl IntoResponse for InvalidPageToken {
+    fn into_response(self) -> axum::response::Response {
+        StatusCode::BAD_REQUEST.into_response()
+    }
+}
+
+/// The cursor a page token points at. Tokens for `/19/list` have no `search`,
+/// and tokens for `/19/search` must have been made for the same search.
+fn page_cursor(
+    state: &QuoteState,
+    token: Option<&str>,
+    search: Option<&str>,
+) -> Result<Option<Cursor>, InvalidPageToken> {
+    let Some(token) = token else {
+        return Ok(None);
+    };
+    match state.cursors.verify(token) {
+        Some(cursor)
+            if cursor.rank.is_some() == search.is_some() && cursor.search.as_deref() == search =>
+        {
+            Ok(Some(cursor))
+        }
+        _ => Err(InvalidPageToken),
+    }
+}
+
+fn etag(quote: &Quote) -> String {
+    format!("\"{}\"", quote.version)
+}
+
+/// Whether the request's `If-Match` header, if it has one, matches the current version of `quote`
+fn if_match(headers: &HeaderMap, quote: &Quote) -> bool {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return true;
+    };
+    let current = etag(quote);
+    value
+        .to_str()
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag == current)
+        })
+        .unwrap_or(false)
+}
+
+fn precondition_failed(id: Uuid) -> Response<Body> {
+    info!("Quote with ID: {id} has changed since it was fetched");
+    Response::builder()
+        .status(StatusCode::PRECONDITION_FAILED)
+        .body("".into())
+        .unwrap()
+}
+
+/// The quote as JSON, tagged with its version so clients can make conditional updates
+fn quote_response(quote: Quote) -> Response<Body> {
+    let etag = HeaderValue::from_str(&etag(&quote)).unwrap();
+    let mut response = Json(quote).into_response();
+    response.headers_mut().insert(ETAG, etag);
+    response
 }
 
 pub(super) async fn reset(State(state): State<QuoteState>) -> Response<String> {
     info!("Resetting quotes");
-    sqlx::query("TRUNCATE TABLE quotes")
-        .execute(&state.pool)
-        .await
-        .unwrap();
-    sqlx::query("TRUNCATE TABLE quotes_pagination")
-        .execute(&state.pool)
-        .await
-        .unwrap();
+    state.store.reset().await.unwrap();
     Response::builder().status(200).body("".into()).unwrap()
 }
 
 pub(super) async fn cite(State(state): State<QuoteState>, Path(id): Path<Uuid>) -> Response<Body> {
     info!("Citing quote with id: {}", id);
-    let quote = get_quote(&state.pool, id).await;
+    let quote = state.store.get(id).await.unwrap();
     match quote {
-        Some(quote) => Json(quote).into_response(),
+        Some(quote) => quote_response(quote),
         None => Response::builder().status(404).body("".into()).unwrap(),
     }
 }
@@ -95,16 +308,16 @@ pub(super) async fn cite(State(state): State<QuoteState>, Path(id): Path<Uuid>)
 pub(super) async fn remove(
     State(state): State<QuoteState>,
     Path(id): Path<Uuid>,
+    headers: HeaderMap,
 ) -> Response<Body> {
     info!("Removing quote with id: {}", id);
-    let quote = get_quote(&state.pool, id).await;
+    let quote = state.store.get(id).await.unwrap();
     match quote {
+        Some(quote) if !if_match(&headers, &quote) => precondition_failed(id),
         Some(quote) => {
-            sqlx::query("DELETE FROM quotes WHERE id = $1")
-                .bind(id)
-                .execute(&state.pool)
-                .await
-                .unwrap();
+            if !state.store.delete(id, quote.version).await.unwrap() {
+                return precondition_failed(id);
+            }
             info!("Successfully removed quote with ID: {id}");
             Json(quote).into_response()
         }
@@ -115,28 +328,46 @@ pub(super) async fn remove(
     }
 }
 
+/// Bring back a removed quote that hasn't been purged yet
+pub(super) async fn restore(
+    State(state): State<QuoteState>,
+    Path(id): Path<Uuid>,
+) -> Response<Body> {
+    info!("Restoring quote with ID: {id}");
+    match state.store.restore(id).await.unwrap() {
+        Some(quote) => quote_response(quote),
+        None => {
+            info!("No removed quote with ID: {id}");
+            Response::builder().status(404).body("".into()).unwrap()
+        }
+    }
+}
+
 pub(super) async fn undo(
     State(state): State<QuoteState>,
     Path(id): Path<Uuid>,
+    headers: HeaderMap,
     Json(new_quote): Json<Quote>,
 ) -> Response<Body> {
     info!("Undoing quote with ID: {id}");
-    let old_quote = get_quote(&state.pool, id).await;
+    let old_quote = state.store.get(id).await.unwrap();
     match old_quote {
+        Some(quote) if !if_match(&headers, &quote) => precondition_failed(id),
         Some(mut updated) => {
             info!("Undoing quote with id: {}, new_quote: {:?}", id, &new_quote);
+            let expected_version = updated.version;
             updated.author = new_quote.author;
             updated.quote = new_quote.quote;
             updated.version += 1;
-            sqlx::query("UPDATE quotes SET author = $1, quote = $2, version = $3 WHERE id = $4")
-                .bind(&updated.author)
-                .bind(&updated.quote)
-                .bind(&updated.version)
-                .bind(id)
-                .execute(&state.pool)
+            if !state
+                .store
+                .update(&updated, expected_version)
                 .await
-                .unwrap();
-            Json(updated).into_response()
+                .unwrap()
+            {
+                return precondition_failed(id);
+            }
+            quote_response(updated)
         }
         None => {
             info!("Failed to undo quote with ID: {}", id,);
@@ -145,26 +376,78 @@ pub(super) async fn undo(
     }
 }
 
+pub(super) async fn history(
+    State(state): State<QuoteState>,
+    Path(id): Path<Uuid>,
+) -> Response<Body> {
+    info!("Listing history of quote with ID: {id}");
+    if state.store.get(id).await.unwrap().is_none() {
+        return Response::builder().status(404).body("".into()).unwrap();
+    }
+    let versions = state.store.history(id).await.unwrap();
+    Json(QuoteHistory { id, versions }).into_response()
+}
+
+/// Restore the content of an earlier revision, recorded as a new version rather than
+/// rewinding, so the revert itself can be undone
+pub(super) async fn revert(
+    State(state): State<QuoteState>,
+    Path((id, version)): Path<(Uuid, i32)>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    info!("Reverting quote with ID: {id} to version {version}");
+    let Some(mut updated) = state.store.get(id).await.unwrap() else {
+        return Response::builder().status(404).body("".into()).unwrap();
+    };
+    if !if_match(&headers, &updated) {
+        return precondition_failed(id);
+    }
+    let Some(revision) = state.store.revision(id, version).await.unwrap() else {
+        info!("Quote with ID: {id} has no version {version}");
+        return Response::builder().status(404).body("".into()).unwrap();
+    };
+    let expected_version = updated.version;
+    updated.author = revision.author;
+    updated.quote = revision.quote;
+    updated.version += 1;
+    if !state
+        .store
+        .update(&updated, expected_version)
+        .await
+        .unwrap()
+    {
+        return precondition_failed(id);
+    }
+    quote_response(updated)
+}
+
 #[axum::debug_handler]
 pub(super) async fn draft(
     State(state): State<QuoteState>,
     Json(new_quote): Json<Quote>,
 ) -> Response<Body> {
     info!("Drafting new quote: {:?}", &new_quote);
-    sqlx::query(
-        "INSERT INTO quotes (id, author, quote, created_at, version) VALUES ($1, $2, $3, $4, $5)",
-    )
-    .bind(new_quote.id)
-    .bind(&new_quote.author)
-    .bind(&new_quote.quote)
-    .bind(&new_quote.created_at)
-    .bind(new_quote.version)
-    .execute(&state.pool)
-    .await
-    .unwrap();
-    let mut response = Json(new_quote).into_response();
-    *response.status_mut() = StatusCode::CREATED;
-    response
+    match state.store.insert(&new_quote).await {
+        Ok(()) => {
+            let mut response = quote_response(new_quote);
+            *response.status_mut() = StatusCode::CREATED;
+            response
+        }
+        Err(QuoteStoreError::Conflict(id)) => {
+            info!("Quote with ID: {id} already exists");
+            Response::builder()
+                .status(StatusCode::CONFLICT)
+                .body("".into())
+                .unwrap()
+        }
+        Err(e) => {
+            error!("Failed to draft quote: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("".into())
+                .unwrap()
+        }
+    }
 }
 
 #[axum::debug_handler]
@@ -172,58 +455,298 @@ pub(super) async fn list(
     State(state): State<QuoteState>,
     query: Query<ListQuery>,
 ) -> Response<Body> {
-    let requested_page = match &query.token {
-        Some(token) => {
-            let token_info =
-                sqlx::query_as::<_, PageToken>("SELECT * FROM quotes_pagination WHERE id = $1")
-                    .bind(&token)
-                    .fetch_optional(&state.pool)
-                    .await
-                    .unwrap();
-            match token_info {
-                Some(token_info) => token_info.page,
-                None => {
-                    return Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body("".into())
-                        .unwrap();
-                }
-            }
-        }
-        None => 0,
+    let cursor = match page_cursor(&state, query.token.as_deref(), None) {
+        Ok(cursor) => cursor,
+        Err(e) => return e.into_response(),
     };
-
+    let page = cursor.as_ref().map_or(1, |cursor| cursor.page);
     info!(
         "Listing quotes with page token: {:?}, Page: {}",
-        query.token,
-        requested_page + 1
+        query.token, page
     );
-    let offset: i32 = requested_page as i32 * 3;
-
-    info!("Offset: {offset}");
-    let quotes = sqlx::query_as::<_, Quote>(
-        "SELECT * FROM quotes ORDER BY created_at ASC OFFSET $1 LIMIT 4",
-    )
-    .bind(offset)
-    .fetch_all(&state.pool)
-    .await
-    .unwrap();
-
-    let mut token = None;
-    if quotes.len() == 4 {
-        let token_id = Alphanumeric.sample_string(&mut thread_rng(), 16);
-        sqlx::query("INSERT INTO quotes_pagination (id, page) VALUES ($1, $2)")
-            .bind(&token_id)
-            .bind(requested_page + 1)
-            .execute(&state.pool)
-            .await
-            .unwrap();
-        token = Some(token_id);
+
+    // Fetch one extra quote to find out whether there's a next page
+    let mut quotes = state
+        .store
+        .list_page(cursor.as_ref(), state.page_size + 1)
+        .await
+        .unwrap();
+
+    let mut next_token = None;
+    if quotes.len() as i64 > state.page_size {
+        quotes.truncate(state.page_size as usize);
+        let last = quotes.last().unwrap();
+        next_token = Some(state.cursors.sign(&Cursor {
+            page: page + 1,
+            created_at: last.created_at,
+            id: last.id,
+            rank: None,
+            search: None,
+        }));
     }
     let list = QuoteList {
-        quotes: quotes.into_iter().take(3).collect(),
-        page: requested_page + 1,
-        next_token: token,
+        quotes,
+        page,
+        next_token,
     };
     Json(list).into_response()
 }
+
+/// Quotes matching a web search style query, best matches first, optionally by a single author
+pub(super) async fn search(
+    State(state): State<QuoteState>,
+    query: Query<SearchQuery>,
+) -> Response<Body> {
+    let search = state.cursors.fingerprint(&query.q, query.author.as_deref());
+    let cursor = match page_cursor(&state, query.token.as_deref(), Some(&search)) {
+        Ok(cursor) => cursor,
+        Err(e) => return e.into_response(),
+    };
+    let page = cursor.as_ref().map_or(1, |cursor| cursor.page);
+    info!(
+        "Searching quotes for {:?} by {:?}, Page: {}",
+        query.q, query.author, page
+    );
+
+    let mut quotes = state
+        .store
+        .search_page(
+            &query.q,
+            query.author.as_deref(),
+            cursor.as_ref(),
+            state.page_size + 1,
+        )
+        .await
+        .unwrap();
+
+    let mut next_token = None;
+    if quotes.len() as i64 > state.page_size {
+        quotes.truncate(state.page_size as usize);
+        let last = quotes.last().unwrap();
+        next_token = Some(state.cursors.sign(&Cursor {
+            page: page + 1,
+            created_at: last.quote.created_at,
+            id: last.quote.id,
+            rank: Some(last.rank),
+            search: Some(search),
+        }));
+    }
+    Json(SearchResults {
+        next_token,
+        page,
+        quotes,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn quote_state() -> QuoteState {
+        QuoteState::from_settings(&Settings::default(), Arc::new(MemoryQuoteStore::default()))
+            .unwrap()
+    }
+
+    fn new_quote(author: &str, quote: &str) -> Json<Quote> {
+        Json(serde_json::from_value(json!({ "author": author, "quote": quote })).unwrap())
+    }
+
+    fn matching(version: i32) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MATCH, format!("\"{version}\"").parse().unwrap());
+        headers
+    }
+
+    async fn body(response: Response<Body>) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    async fn drafted(state: &QuoteState, author: &str, quote: &str) -> Uuid {
+        let response = draft(State(state.clone()), new_quote(author, quote)).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        serde_json::from_value(body(response).await["id"].clone()).unwrap()
+    }
+
+    async fn list_page(state: &QuoteState, token: Option<String>) -> Response<Body> {
+        list(State(state.clone()), Query(ListQuery { token })).await
+    }
+
+    async fn search_page(state: &QuoteState, q: &str, token: Option<String>) -> Response<Body> {
+        let query = SearchQuery {
+            q: q.to_string(),
+            author: None,
+            token,
+        };
+        search(State(state.clone()), Query(query)).await
+    }
+
+    #[tokio::test]
+    async fn duplicate_drafts_conflict() {
+        let state = quote_state();
+        let id = drafted(&state, "Santa", "Ho ho ho").await;
+        let duplicate = Json(
+            serde_json::from_value(json!({ "id": id, "author": "Grinch", "quote": "Mine now" }))
+                .unwrap(),
+        );
+        let response = draft(State(state.clone()), duplicate).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_is_rejected() {
+        let state = quote_state();
+        let id = drafted(&state, "Santa", "Ho ho ho").await;
+
+        let response = undo(
+            State(state.clone()),
+            Path(id),
+            matching(1),
+            new_quote("Santa", "Ho ho"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ETAG], "\"2\"");
+
+        let response = undo(
+            State(state.clone()),
+            Path(id),
+            matching(1),
+            new_quote("Santa", "Ho"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = remove(State(state.clone()), Path(id), matching(1)).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let response = remove(State(state.clone()), Path(id), matching(2)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn revert_adds_to_history() {
+        let state = quote_state();
+        let id = drafted(&state, "Santa", "Ho ho ho").await;
+        undo(
+            State(state.clone()),
+            Path(id),
+            HeaderMap::new(),
+            new_quote("Santa", "Bah humbug"),
+        )
+        .await;
+
+        let reverted = revert(State(state.clone()), Path((id, 1)), matching(2)).await;
+        assert_eq!(reverted.status(), StatusCode::OK);
+        let reverted = body(reverted).await;
+        assert_eq!(reverted["quote"], "Ho ho ho");
+        assert_eq!(reverted["version"], 3);
+
+        let missing = revert(State(state.clone()), Path((id, 9)), HeaderMap::new()).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+
+        let history = body(history(State(state.clone()), Path(id)).await).await;
+        let versions: Vec<(i64, &str)> = history["versions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|version| {
+                (
+                    version["version"].as_i64().unwrap(),
+                    version["quote"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            versions,
+            [(1, "Ho ho ho"), (2, "Bah humbug"), (3, "Ho ho ho")]
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_quotes_can_be_restored() {
+        let state = quote_state();
+        let id = drafted(&state, "Santa", "Ho ho ho").await;
+        remove(State(state.clone()), Path(id), HeaderMap::new()).await;
+
+        let response = cite(State(state.clone()), Path(id)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let listed = body(list_page(&state, None).await).await;
+        assert_eq!(listed["quotes"], json!([]));
+
+        let response = restore(State(state.clone()), Path(id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = cite(State(state.clone()), Path(id)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = restore(State(state.clone()), Path(id)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn list_pages_through_every_quote() {
+        let state = quote_state();
+        for number in 0..7 {
+            drafted(&state, "Elf", &format!("Quote {number}")).await;
+        }
+
+        let (mut seen, mut pages, mut token) = (Vec::new(), Vec::new(), None);
+        loop {
+            let page = body(list_page(&state, token).await).await;
+            pages.push(page["page"].as_i64().unwrap());
+            for quote in page["quotes"].as_array().unwrap() {
+                seen.push(quote["id"].as_str().unwrap().to_string());
+            }
+            match page["next_token"].as_str() {
+                Some(next) => token = Some(next.to_string()),
+                None => break,
+            }
+        }
+        assert_eq!(pages, [1, 2, 3]);
+        assert_eq!(seen.len(), 7);
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 7);
+
+        let forged = list_page(&state, Some("not.a-token".to_string())).await;
+        assert_eq!(forged.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn search_pages_by_rank() {
+        let state = quote_state();
+        drafted(&state, "Cookie", "Cookies are great").await;
+        for number in 0..4 {
+            drafted(&state, "Elf", &format!("<b>{number}</b> cookies and milk")).await;
+        }
+        drafted(&state, "Elf", "Just milk").await;
+
+        let first = body(search_page(&state, "cookies", None).await).await;
+        let token = first["next_token"].as_str().unwrap().to_string();
+        let second = body(search_page(&state, "cookies", Some(token.clone())).await).await;
+        assert!(second["next_token"].is_null());
+
+        let hits: Vec<&Value> = first["quotes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .chain(second["quotes"].as_array().unwrap())
+            .collect();
+        assert_eq!(hits.len(), 5);
+        assert_eq!(hits[0]["author"], "Cookie");
+        let ranks: Vec<f64> = hits
+            .iter()
+            .map(|hit| hit["rank"].as_f64().unwrap())
+            .collect();
+        assert!(ranks.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(hits
+            .iter()
+            .all(|hit| !hit["snippet"].as_str().unwrap().contains("<b>")));
+
+        let other_search = search_page(&state, "milk", Some(token.clone())).await;
+        assert_eq!(other_search.status(), StatusCode::BAD_REQUEST);
+        let listed = list_page(&state, Some(token)).await;
+        assert_eq!(listed.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{crypto, Algorithm, DecodingKey, EncodingKey};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::config::Settings;
+
+/// Where a page of quotes starts: just after the last quote of the page before it
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Cursor {
+    /// The 1-based number of the page the cursor leads to
+    pub(crate) page: i32,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) id: Uuid,
+    /// The rank of the last result, for cursors through `/19/search` results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) rank: Option<f64>,
+    /// The [`CursorSigner::fingerprint`] of the search the cursor pages through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) search: Option<String>,
+}
+
+/// Signs page tokens so clients can't forge a position, keyed by `QUOTE_CURSOR_SECRET`
+#[derive(Clone)]
+pub(crate) struct CursorSigner {
+    secret: Vec<u8>,
+}
+
+impl fmt::Debug for CursorSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CursorSigner").finish_non_exhaustive()
+    }
+}
+
+impl CursorSigner {
+    pub(crate) fn from_settings(settings: &Settings) -> Self {
+        let secret = match settings.get("QUOTE_CURSOR_SECRET") {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                warn!("No QUOTE_CURSOR_SECRET configured, page tokens won't survive a restart");
+                rand::thread_rng().gen::<[u8; 32]>().to_vec()
+            }
+        };
+        Self { secret }
+    }
+
+    /// HS256 of `message` under the secret, base64url encoded
+    fn mac(&self, message: &[u8]) -> String {
+        crypto::sign(
+            message,
+            &EncodingKey::from_secret(&self.secret),
+            Algorithm::HS256,
+        )
+        .unwrap()
+    }
+
+    /// An opaque token for `cursor`: its JSON and an HS256 signature, both base64url encoded
+    pub(crate) fn sign(&self, cursor: &Cursor) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(cursor).unwrap());
+        let signature = self.mac(payload.as_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// A keyed hash of a search and its author filter, so page tokens from one search
+    /// can't be used to page through another
+    pub(crate) fn fingerprint(&self, query: &str, author: Option<&str>) -> String {
+        self.mac(&serde_json::to_vec(&(query, author)).unwrap())
+    }
+
+    /// The cursor in `token`, if it's well formed and was signed by us
+    pub(crate) fn verify(&self, token: &str) -> Option<Cursor> {
+        let (payload, signature) = token.split_once('.')?;
+        let valid = crypto::verify(
+            signature,
+            payload.as_bytes(),
+            &DecodingKey::from_secret(&self.secret),
+            Algorithm::HS256,
+        )
+        .ok()?;
+        if !valid {
+            return None;
+        }
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    cursor::Cursor,
+    store::{QuoteStore, QuoteStoreError},
+    Quote, QuoteVersion, SearchHit,
+};
+
+/// A quote along with everything the Postgres tables keep about it
+struct Entry {
+    quote: Quote,
+    deleted_at: Option<DateTime<Utc>>,
+    versions: Vec<QuoteVersion>,
+}
+
+impl Entry {
+    fn record_version(&mut self) {
+        self.versions.push(QuoteVersion {
+            version: self.quote.version,
+            author: self.quote.author.clone(),
+            quote: self.quote.quote.clone(),
+            recorded_at: Utc::now(),
+        });
+    }
+}
+
+/// Keeps quotes in memory, for running without a database. Nothing survives a restart.
+#[derive(Default)]
+pub(crate) struct MemoryQuoteStore {
+    entries: Mutex<HashMap<Uuid, Entry>>,
+}
+
+/// Lowercased words of `text`, without surrounding punctuation
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+}
+
+/// Whether `word` matches a search term, treating the term as a prefix
+/// so "cookie" finds "cookies" the way stemming would
+fn matches(word: &str, terms: &[String]) -> bool {
+    terms.iter().any(|term| word.starts_with(term.as_str()))
+}
+
+/// A rough stand-in for Postgres full-text search: every term has to appear in the author
+/// or the quote, and matches in the author count for more
+fn search_hit(quote: &Quote, terms: &[String]) -> Option<SearchHit> {
+    let found = |text: &str| words(text).filter(|word| matches(word, terms)).count();
+    let all_present = terms.iter().all(|term| {
+        words(&quote.author)
+            .chain(words(&quote.quote))
+            .any(|word| word.starts_with(term.as_str()))
+    });
+    if !all_present {
+        return None;
+    }
+    let snippet = quote
+        .quote
+        .split(' ')
+        .map(|word| {
+            let bare = word.trim_matches(|c: char| !c.is_alphanumeric());
+            let escaped = html_escape::encode_safe(word);
+            match !bare.is_empty() && matches(&bare.to_lowercase(), terms) {
+                true => {
+                    let bare = html_escape::encode_safe(bare);
+                    escaped.replacen(bare.as_ref(), &format!("<mark>{bare}</mark>"), 1)
+                }
+                false => escaped.into_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(SearchHit {
+        quote: quote.clone(),
+        rank: (found(&quote.author) as f64 + 0.1 * found(&quote.quote) as f64)
+            / (1 + words(&quote.quote).count()) as f64,
+        snippet,
+    })
+}
+
+#[async_trait]
+impl QuoteStore for MemoryQuoteStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        let entries = self.entries.lock().await;
+        Ok(entries
+            .get(&id)
+            .filter(|entry| entry.deleted_at.is_none())
+            .map(|entry| entry.quote.clone()))
+    }
+
+    async fn insert(&self, quote: &Quote) -> Result<(), QuoteStoreError> {
+        let mut entries = self.entries.lock().await;
+        if entries.contains_key(&quote.id) {
+            return Err(QuoteStoreError::Conflict(quote.id));
+        }
+        let mut entry = Entry {
+            quote: quote.clone(),
+            deleted_at: None,
+            versions: Vec::new(),
+        };
+        entry.record_version();
+        entries.insert(quote.id, entry);
+        Ok(())
+    }
+
+    async fn update(&self, quote: &Quote, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(&quote.id) {
+            Some(entry)
+                if entry.deleted_at.is_none() && entry.quote.version == expected_version =>
+            {
+                entry.quote.author = quote.author.clone();
+                entry.quote.quote = quote.quote.clone();
+                entry.quote.version = quote.version;
+                entry.record_version();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let mut entries = self.entries.lock().await;
+        match entries.get_mut(&id) {
+            Some(entry)
+                if entry.deleted_at.is_none() && entry.quote.version == expected_version =>
+            {
+                entry.deleted_at = Some(Utc::now());
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn restore(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        let mut entries = self.entries.lock().await;
+        Ok(entries
+            .get_mut(&id)
+            .filter(|entry| entry.deleted_at.is_some())
+            .map(|entry| {
+                entry.deleted_at = None;
+                entry.quote.clone()
+            }))
+    }
+
+    async fn history(&self, id: Uuid) -> Result<Vec<QuoteVersion>, QuoteStoreError> {
+        let entries = self.entries.lock().await;
+        Ok(entries
+            .get(&id)
+            .map(|entry| entry.versions.clone())
+            .unwrap_or_default())
+    }
+
+    async fn revision(
+        &self,
+        id: Uuid,
+        version: i32,
+    ) -> Result<Option<QuoteVersion>, QuoteStoreError> {
+        let entries = self.entries.lock().await;
+        Ok(entries.get(&id).and_then(|entry| {
+            entry
+                .versions
+                .iter()
+                .find(|revision| revision.version == version)
+                .cloned()
+        }))
+    }
+
+    async fn list_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Quote>, QuoteStoreError> {
+        let entries = self.entries.lock().await;
+        let mut quotes: Vec<Quote> = entries
+            .values()
+            .filter(|entry| entry.deleted_at.is_none())
+            .map(|entry| entry.quote.clone())
+            .filter(|quote| {
+                after.is_none_or(|cursor| {
+                    (quote.created_at, quote.id) > (cursor.created_at, cursor.id)
+                })
+            })
+            .collect();
+        quotes.sort_by_key(|quote| (quote.created_at, quote.id));
+        quotes.truncate(limit as usize);
+        Ok(quotes)
+    }
+
+    async fn search_page(
+        &self,
+        query: &str,
+        author: Option<&str>,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, QuoteStoreError> {
+        let terms: Vec<String> = words(query).collect();
+        if terms.is_empty() {
+            return Ok(Vec::new());
+        }
+        let entries = self.entries.lock().await;
+        let mut hits: Vec<SearchHit> = entries
+            .values()
+            .filter(|entry| entry.deleted_at.is_none())
+            .filter(|entry| {
+                author.is_none_or(|author| entry.quote.author.eq_ignore_ascii_case(author))
+            })
+            .filter_map(|entry| search_hit(&entry.quote, &terms))
+            .filter(|hit| match after {
+                Some(&Cursor {
+                    rank: Some(rank),
+                    id,
+                    ..
+                }) => hit.rank < rank || (hit.rank == rank && hit.quote.id > id),
+                _ => true,
+            })
+            .collect();
+        hits.sort_by(|a, b| {
+            b.rank
+                .total_cmp(&a.rank)
+                .then_with(|| a.quote.id.cmp(&b.quote.id))
+        });
+        hits.truncate(limit as usize);
+        Ok(hits)
+    }
+
+    async fn purge_removed(&self, retention_seconds: i64) -> Result<u64, QuoteStoreError> {
+        let cutoff = Utc::now() - Duration::seconds(retention_seconds);
+        let mut entries = self.entries.lock().await;
+        let before = entries.len();
+        entries.retain(|_, entry| entry.deleted_at.is_none_or(|deleted| deleted >= cutoff));
+        Ok((before - entries.len()) as u64)
+    }
+
+    async fn reset(&self) -> Result<(), QuoteStoreError> {
+        self.entries.lock().await.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use axum::async_trait;
+use chrono::{Duration, Utc};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+use super::{
+    cursor::Cursor,
+    store::{QuoteStore, QuoteStoreError},
+    Quote, QuoteVersion, SearchHit,
+};
+
+/// Keeps quotes in a SQLite database, for local development and small deployments
+#[derive(Clone, Debug)]
+pub(crate) struct SqliteQuoteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteQuoteStore {
+    /// Connect to the database at `url`, creating it if need be, and bring its tables up to date
+    pub(crate) async fn connect(url: &str) -> Result<Self, super::StoreError> {
+        let options = url
+            .parse::<sqlx::sqlite::SqliteConnectOptions>()?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        Ok(Self { pool })
+    }
+}
+
+/// The query as FTS5 understands it: every word has to appear, and none of them are operators
+fn match_expression(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Bracket highlighted terms in the snippet while the text is still raw, to be swapped
+/// for <mark> tags once it's been HTML escaped
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_END: char = '\u{3}';
+
+fn mark_highlights(snippet: &str) -> String {
+    html_escape::encode_safe(snippet)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_END, "</mark>")
+}
+
+async fn record_version(
+    tx: &mut Transaction<'_, Sqlite>,
+    quote: &Quote,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO quote_versions (quote_id, version, author, quote, recorded_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )
+    .bind(quote.id)
+    .bind(quote.version)
+    .bind(&quote.author)
+    .bind(&quote.quote)
+    .bind(Utc::now())
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl QuoteStore for SqliteQuoteStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>("SELECT * FROM quotes WHERE id = ?1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn insert(&self, quote: &Quote) -> Result<(), QuoteStoreError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO quotes (id, author, quote, created_at, version) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )
+        .bind(quote.id)
+        .bind(&quote.author)
+        .bind(&quote.quote)
+        .bind(quote.created_at)
+        .bind(quote.version)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| QuoteStoreError::on_insert(quote.id, e))?;
+        record_version(&mut tx, quote).await?;
+        Ok(tx.commit().await?)
+    }
+
+    async fn update(&self, quote: &Quote, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE quotes SET author = ?1, quote = ?2, version = ?3 WHERE id = ?4 AND version = ?5 AND deleted_at IS NULL",
+        )
+        .bind(&quote.author)
+        .bind(&quote.quote)
+        .bind(quote.version)
+        .bind(quote.id)
+        .bind(expected_version)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+        record_version(&mut tx, quote).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let removed = sqlx::query(
+            "UPDATE quotes SET deleted_at = ?1 WHERE id = ?2 AND version = ?3 AND deleted_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .bind(expected_version)
+        .execute(&self.pool)
+        .await?;
+        Ok(removed.rows_affected() > 0)
+    }
+
+    async fn restore(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>(
+            "UPDATE quotes SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn history(&self, id: Uuid) -> Result<Vec<QuoteVersion>, QuoteStoreError> {
+        sqlx::query_as::<_, QuoteVersion>(
+            "SELECT version, author, quote, recorded_at FROM quote_versions WHERE quote_id = ?1 ORDER BY version",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn revision(
+        &self,
+        id: Uuid,
+        version: i32,
+    ) -> Result<Option<QuoteVersion>, QuoteStoreError> {
+        sqlx::query_as::<_, QuoteVersion>(
+            "SELECT version, author, quote, recorded_at FROM quote_versions WHERE quote_id = ?1 AND version = ?2",
+        )
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn list_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>(
+            "SELECT * FROM quotes WHERE deleted_at IS NULL AND (?1 IS NULL OR (created_at, id) > (?1, ?2)) ORDER BY created_at, id LIMIT ?3",
+        )
+        .bind(after.map(|cursor| cursor.created_at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn search_page(
+        &self,
+        query: &str,
+        author: Option<&str>,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, QuoteStoreError> {
+        let expression = match_expression(query);
+        if expression.is_empty() {
+            return Ok(Vec::new());
+        }
+        // bm25 scores better matches lower, so negate it to rank like ts_rank does,
+        // weighting the author above the quote
+        let mut hits = sqlx::query_as::<_, SearchHit>(
+            "WITH matches AS (
+                SELECT quotes.id, quotes.author, quotes.quote, quotes.created_at, quotes.version,
+                    -bm25(quotes_fts, 10.0, 1.0) AS rank,
+                    highlight(quotes_fts, 1, char(2), char(3)) AS snippet
+                FROM quotes_fts JOIN quotes ON quotes.seq = quotes_fts.rowid
+                WHERE quotes_fts MATCH ?1 AND quotes.deleted_at IS NULL
+                    AND (?2 IS NULL OR lower(quotes.author) = lower(?2))
+            )
+            SELECT * FROM matches
+            WHERE ?3 IS NULL OR rank < ?3 OR (rank = ?3 AND id > ?4)
+            ORDER BY rank DESC, id
+            LIMIT ?5",
+        )
+        .bind(expression)
+        .bind(author)
+        .bind(after.and_then(|cursor| cursor.rank))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        for hit in hits.iter_mut() {
+            hit.snippet = mark_highlights(&hit.snippet);
+        }
+        Ok(hits)
+    }
+
+    async fn purge_removed(&self, retention_seconds: i64) -> Result<u64, QuoteStoreError> {
+        let purged = sqlx::query("DELETE FROM quotes WHERE deleted_at < ?1")
+            .bind(Utc::now() - Duration::seconds(retention_seconds))
+            .execute(&self.pool)
+            .await?;
+        Ok(purged.rows_affected())
+    }
+
+    async fn reset(&self) -> Result<(), QuoteStoreError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM quote_versions")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM quotes").execute(&mut *tx).await?;
+        Ok(tx.commit().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+
+    async fn store() -> SqliteQuoteStore {
+        // Every connection to sqlite::memory: gets its own database, so keep to one
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("./migrations/sqlite")
+            .run(&pool)
+            .await
+            .unwrap();
+        SqliteQuoteStore { pool }
+    }
+
+    async fn insert(store: &SqliteQuoteStore, author: &str, quote: &str) -> Quote {
+        let quote: Quote =
+            serde_json::from_value(json!({ "author": author, "quote": quote })).unwrap();
+        store.insert(&quote).await.unwrap();
+        quote
+    }
+
+    async fn authors(store: &SqliteQuoteStore, query: &str) -> Vec<String> {
+        let hits = store.search_page(query, None, None, 10).await.unwrap();
+        hits.into_iter().map(|hit| hit.quote.author).collect()
+    }
+
+    #[tokio::test]
+    async fn search_follows_writes() {
+        let store = store().await;
+        let mut quote = insert(&store, "Santa", "Ho ho ho").await;
+        assert_eq!(authors(&store, "ho").await, ["Santa"]);
+
+        quote.quote = "Merry Christmas".to_string();
+        quote.version = 2;
+        assert!(store.update(&quote, 1).await.unwrap());
+        assert!(authors(&store, "ho").await.is_empty());
+        assert_eq!(authors(&store, "merry").await, ["Santa"]);
+
+        assert!(store.delete(quote.id, 2).await.unwrap());
+        assert!(authors(&store, "merry").await.is_empty());
+        store.restore(quote.id).await.unwrap();
+        assert_eq!(authors(&store, "merry").await, ["Santa"]);
+
+        sqlx::query("UPDATE quotes SET deleted_at = ?1")
+            .bind(Utc::now() - Duration::days(1))
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(store.purge_removed(60).await.unwrap(), 1);
+        let indexed: i64 =
+            sqlx::query_scalar("SELECT count(*) FROM quotes_fts WHERE quotes_fts MATCH 'merry'")
+                .fetch_one(&store.pool)
+                .await
+                .unwrap();
+        assert_eq!(indexed, 0);
+    }
+
+    #[tokio::test]
+    async fn search_ranks_authors_first() {
+        let store = store().await;
+        insert(&store, "Elf", "Cookies and milk for the reindeer").await;
+        insert(&store, "Reindeer", "Carrots please").await;
+        insert(&store, "Rudolph", "Reindeer reindeer reindeer").await;
+        assert_eq!(
+            authors(&store, "reindeer").await,
+            ["Reindeer", "Rudolph", "Elf"]
+        );
+
+        let hits = store.search_page("reindeer", None, None, 10).await.unwrap();
+        assert!(hits.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+        assert_eq!(
+            hits[2].snippet,
+            "Cookies and milk for the <mark>reindeer</mark>"
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_pick_up_where_they_left_off() {
+        let store = store().await;
+        let mut inserted = Vec::new();
+        for number in 0..5 {
+            inserted.push(
+                insert(&store, "Elf", &format!("Gift number {number}"))
+                    .await
+                    .id,
+            );
+        }
+
+        let mut listed = Vec::new();
+        let mut after = None;
+        loop {
+            let page = store.list_page(after.as_ref(), 2).await.unwrap();
+            let Some(last) = page.last() else { break };
+            after = Some(Cursor {
+                page: 1,
+                created_at: last.created_at,
+                id: last.id,
+                rank: None,
+                search: None,
+            });
+            listed.extend(page.iter().map(|quote| quote.id));
+        }
+        inserted.sort();
+        listed.sort();
+        assert_eq!(listed, inserted);
+
+        let mut found = Vec::new();
+        let mut after = None;
+        loop {
+            let page = store
+                .search_page("gift", None, after.as_ref(), 2)
+                .await
+                .unwrap();
+            let Some(last) = page.last() else { break };
+            after = Some(Cursor {
+                page: 1,
+                created_at: last.quote.created_at,
+                id: last.quote.id,
+                rank: Some(last.rank),
+                search: None,
+            });
+            found.extend(page.iter().map(|hit| hit.quote.id));
+        }
+        found.sort();
+        assert_eq!(found, inserted);
+    }
+}
@@ -0,0 +1,269 @@
+use axum::async_trait;
+use sqlx::{PgPool, Postgres, Transaction};
+use thiserror::Error;
+use uuid::Uuid;
+
+use super::{cursor::Cursor, Quote, QuoteVersion, SearchHit};
+
+#[derive(Debug, Error)]
+pub(crate) enum QuoteStoreError {
+    #[error("Quote {0} already exists")]
+    Conflict(Uuid),
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl QuoteStoreError {
+    /// [`QuoteStoreError::Conflict`] if inserting `id` broke a unique constraint
+    pub(crate) fn on_insert(id: Uuid, error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(ref e) if e.is_unique_violation() => Self::Conflict(id),
+            error => Self::Database(error),
+        }
+    }
+}
+
+/// Where the quote book keeps its quotes and their history.
+/// Removed quotes are kept, out of sight of everything but [`QuoteStore::restore`],
+/// until [`QuoteStore::purge_removed`] deletes them for good.
+#[async_trait]
+pub(crate) trait QuoteStore: Send + Sync {
+    /// A quote that hasn't been removed
+    async fn get(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError>;
+
+    /// Add a new quote, recording it as the first entry in its history
+    async fn insert(&self, quote: &Quote) -> Result<(), QuoteStoreError>;
+
+    /// Save a new revision of a quote along with its history entry, as long as nobody else
+    /// has changed it since it was at `expected_version`
+    async fn update(&self, quote: &Quote, expected_version: i32) -> Result<bool, QuoteStoreError>;
+
+    /// Remove a quote, as long as it's still at `expected_version`
+    async fn delete(&self, id: Uuid, expected_version: i32) -> Result<bool, QuoteStoreError>;
+
+    /// Bring back a removed quote that hasn't been purged yet
+    async fn restore(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError>;
+
+    /// Every revision of a quote, oldest first
+    async fn history(&self, id: Uuid) -> Result<Vec<QuoteVersion>, QuoteStoreError>;
+
+    async fn revision(
+        &self,
+        id: Uuid,
+        version: i32,
+    ) -> Result<Option<QuoteVersion>, QuoteStoreError>;
+
+    /// Up to `limit` quotes in creation order, starting after `after`
+    async fn list_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Quote>, QuoteStoreError>;
+
+    /// Up to `limit` quotes matching `query`, best matches first, starting after `after`
+    async fn search_page(
+        &self,
+        query: &str,
+        author: Option<&str>,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, QuoteStoreError>;
+
+    /// Permanently delete quotes removed more than `retention_seconds` ago
+    async fn purge_removed(&self, retention_seconds: i64) -> Result<u64, QuoteStoreError>;
+
+    /// Delete every quote and its history
+    async fn reset(&self) -> Result<(), QuoteStoreError>;
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PgQuoteStore {
+    pool: PgPool,
+}
+
+impl PgQuoteStore {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+/// Record `quote` as it now stands in its history
+async fn record_version(
+    tx: &mut Transaction<'_, Postgres>,
+    quote: &Quote,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO quote_versions (quote_id, version, author, quote) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(quote.id)
+    .bind(quote.version)
+    .bind(&quote.author)
+    .bind(&quote.quote)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[async_trait]
+impl QuoteStore for PgQuoteStore {
+    async fn get(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>("SELECT * FROM quotes WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn insert(&self, quote: &Quote) -> Result<(), QuoteStoreError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO quotes (id, author, quote, created_at, version) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(quote.id)
+        .bind(&quote.author)
+        .bind(&quote.quote)
+        .bind(quote.created_at)
+        .bind(quote.version)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| QuoteStoreError::on_insert(quote.id, e))?;
+        record_version(&mut tx, quote).await?;
+        Ok(tx.commit().await?)
+    }
+
+    async fn update(&self, quote: &Quote, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let mut tx = self.pool.begin().await?;
+        let updated = sqlx::query(
+            "UPDATE quotes SET author = $1, quote = $2, version = $3 WHERE id = $4 AND version = $5 AND deleted_at IS NULL",
+        )
+        .bind(&quote.author)
+        .bind(&quote.quote)
+        .bind(quote.version)
+        .bind(quote.id)
+        .bind(expected_version)
+        .execute(&mut *tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Ok(false);
+        }
+        record_version(&mut tx, quote).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn delete(&self, id: Uuid, expected_version: i32) -> Result<bool, QuoteStoreError> {
+        let removed = sqlx::query(
+            "UPDATE quotes SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1 AND version = $2 AND deleted_at IS NULL",
+        )
+        .bind(id)
+        .bind(expected_version)
+        .execute(&self.pool)
+        .await?;
+        Ok(removed.rows_affected() > 0)
+    }
+
+    async fn restore(&self, id: Uuid) -> Result<Option<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>(
+            "UPDATE quotes SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn history(&self, id: Uuid) -> Result<Vec<QuoteVersion>, QuoteStoreError> {
+        sqlx::query_as::<_, QuoteVersion>(
+            "SELECT version, author, quote, recorded_at FROM quote_versions WHERE quote_id = $1 ORDER BY version",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn revision(
+        &self,
+        id: Uuid,
+        version: i32,
+    ) -> Result<Option<QuoteVersion>, QuoteStoreError> {
+        sqlx::query_as::<_, QuoteVersion>(
+            "SELECT version, author, quote, recorded_at FROM quote_versions WHERE quote_id = $1 AND version = $2",
+        )
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn list_page(
+        &self,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<Quote>, QuoteStoreError> {
+        sqlx::query_as::<_, Quote>(
+            "SELECT * FROM quotes WHERE deleted_at IS NULL AND ($1::TIMESTAMPTZ IS NULL OR (created_at, id) > ($1, $2)) ORDER BY created_at, id LIMIT $3",
+        )
+        .bind(after.map(|cursor| cursor.created_at))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn search_page(
+        &self,
+        query: &str,
+        author: Option<&str>,
+        after: Option<&Cursor>,
+        limit: i64,
+    ) -> Result<Vec<SearchHit>, QuoteStoreError> {
+        // The quote is HTML escaped the way `html_escape::encode_safe` does it before
+        // highlighting, so the only markup in the snippet is the <mark> tags
+        sqlx::query_as::<_, SearchHit>(
+            "WITH matches AS (
+                SELECT id, author, quote, created_at, version,
+                    ts_rank(search, websearch_to_tsquery('english', $1))::FLOAT8 AS rank
+                FROM quotes
+                WHERE deleted_at IS NULL AND search @@ websearch_to_tsquery('english', $1)
+                    AND ($2::TEXT IS NULL OR lower(author) = lower($2))
+            )
+            SELECT *, ts_headline('english',
+                replace(replace(replace(replace(replace(replace(quote,
+                    '&', '&amp;'), '<', '&lt;'), '>', '&gt;'), '\"', '&quot;'), '''', '&#x27;'), '/', '&#x2F;'),
+                websearch_to_tsquery('english', $1),
+                'StartSel=<mark>, StopSel=</mark>') AS snippet
+            FROM matches
+            WHERE $3::FLOAT8 IS NULL OR rank < $3 OR (rank = $3 AND id > $4)
+            ORDER BY rank DESC, id
+            LIMIT $5",
+        )
+        .bind(query)
+        .bind(author)
+        .bind(after.and_then(|cursor| cursor.rank))
+        .bind(after.map(|cursor| cursor.id))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn purge_removed(&self, retention_seconds: i64) -> Result<u64, QuoteStoreError> {
+        let purged = sqlx::query(
+            "DELETE FROM quotes WHERE deleted_at < CURRENT_TIMESTAMP - make_interval(secs => $1)",
+        )
+        .bind(retention_seconds as f64)
+        .execute(&self.pool)
+        .await?;
+        Ok(purged.rows_affected())
+    }
+
+    async fn reset(&self) -> Result<(), QuoteStoreError> {
+        sqlx::query("TRUNCATE TABLE quotes, quote_versions")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderMap, Response, StatusCode,
+    },
+    middleware::Next,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use super::{cookies, keys::TokenError, GiftState, REFRESH_TOKEN_USE};
+
+/// Role required by the destructive admin routes
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("No bearer token or gift cookie was presented")]
+    Missing,
+    #[error(transparent)]
+    Invalid(#[from] TokenError),
+    #[error("Token {0} has been revoked")]
+    Revoked(Uuid),
+    #[error("Tokens without an id can't be revoked, so they aren't accepted")]
+    Unrevocable,
+    #[error("Refresh tokens can only be exchanged at /16/refresh")]
+    RefreshToken,
+    #[error("The `{0}` role is required")]
+    Forbidden(&'static str),
+    #[error("Failed to check revocations: {0}")]
+    Store(#[from] sqlx::Error),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self {
+            AuthError::Missing
+            | AuthError::Invalid(_)
+            | AuthError::Revoked(_)
+            | AuthError::Unrevocable
+            | AuthError::RefreshToken => {
+                info!("Unauthenticated request: {self}");
+                StatusCode::UNAUTHORIZED
+            }
+            AuthError::Forbidden(_) => {
+                info!("Forbidden request: {self}");
+                StatusCode::FORBIDDEN
+            }
+            AuthError::Store(ref e) => {
+                error!("{e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        let mut response = Response::builder().status(status);
+        if status == StatusCode::UNAUTHORIZED {
+            response = response.header(WWW_AUTHENTICATE, "Bearer");
+        }
+        response.body("".into()).unwrap()
+    }
+}
+
+/// The claims authentication cares about. Roles are only ever set by whoever holds
+/// the signing keys; `/16/wrap` nests its payload under `contents`, so gifts can't grant them.
+#[derive(Debug, Deserialize)]
+struct AuthClaims {
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    jti: Option<Uuid>,
+    #[serde(default)]
+    fam: Option<Uuid>,
+    #[serde(default)]
+    token_use: Option<String>,
+}
+
+/// Whoever presented a valid token, as attached to the request by [`authenticate`]
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub subject: Option<String>,
+    pub roles: Vec<String>,
+}
+
+impl Principal {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|held| held == role)
+    }
+}
+
+impl GiftState {
+    /// Verify the request's bearer token, falling back to its gift cookie
+    pub(crate) async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        let bearer = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        let token = bearer
+            .or_else(|| cookies::gift_cookie(headers))
+            .ok_or(AuthError::Missing)?;
+
+        let claims = self.codec.open::<AuthClaims>(&token)?.claims;
+        if claims.token_use.as_deref() == Some(REFRESH_TOKEN_USE) {
+            return Err(AuthError::RefreshToken);
+        }
+        let jti = claims.jti.ok_or(AuthError::Unrevocable)?;
+        self.check_revocation(Some(jti), claims.fam).await?;
+        Ok(Principal {
+            subject: claims.sub,
+            roles: claims.roles,
+        })
+    }
+
+    /// Fail if the token, or the refresh token family it was issued under, has been revoked
+    pub(crate) async fn check_revocation(
+        &self,
+        jti: Option<Uuid>,
+        family: Option<Uuid>,
+    ) -> Result<(), AuthError> {
+        let ids: Vec<Uuid> = [jti, family].into_iter().flatten().collect();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        match self.revocations.is_revoked(&ids).await? {
+            true => Err(AuthError::Revoked(ids[0])),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Attach the [`Principal`] to requests carrying a valid token, letting the rest through anonymously
+pub async fn authenticate(
+    State(state): State<GiftState>,
+    mut request: Request,
+    next: Next,
+) -> axum::response::Response {
+    match state.authenticate(request.headers()).await {
+        Ok(principal) => {
+            request.extensions_mut().insert(principal);
+        }
+        Err(AuthError::Missing) => (),
+        Err(e) => info!("Continuing anonymously: {e}"),
+    }
+    next.run(request).await
+}
+
+/// Only let requests through with a valid token holding the admin role
+pub async fn require_admin(
+    State(state): State<GiftState>,
+    mut request: Request,
+    next: Next,
+) -> axum::response::Response {
+    let principal = match state.authenticate(request.headers()).await {
+        Ok(principal) => principal,
+        Err(e) => return e.into_response(),
+    };
+    if !principal.has_role(ADMIN_ROLE) {
+        return AuthError::Forbidden(ADMIN_ROLE).into_response();
+    }
+    info!(
+        "Admin request to {} by {}",
+        request.uri(),
+        principal.subject.as_deref().unwrap_or("anonymous")
+    );
+    request.extensions_mut().insert(principal);
+    next.run(request).await
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = AuthError;
+
+    /// Requires one of the auth middleware to have run on the route
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or(AuthError::Missing)
+    }
+}
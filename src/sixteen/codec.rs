@@ -0,0 +1,141 @@
+use jsonwebtoken::{jwk::JwkSet, TokenData};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    compression,
+    issuers::{DecodeError, IssuerRegistry},
+    jwe::{self, GiftCipher, JweError},
+    keys::{KeyRing, TokenError},
+    Claim, SetupError,
+};
+use crate::config::{ConfigError, Settings};
+
+/// Signs, compresses and encrypts gift tokens, and verifies them along with tokens from
+/// trusted issuers. None of it needs the database, so the `jwt` tool shares it with the server.
+pub struct GiftCodec {
+    keys: KeyRing,
+    cipher: Option<GiftCipher>,
+    /// Whether new gifts are encrypted, set with `GIFT_ENCRYPT`.
+    /// Encrypted gifts are opened whenever a key is configured.
+    encrypt: bool,
+    /// Whether gift contents are DEFLATE compressed, set with `GIFT_COMPRESS`
+    compress: bool,
+    issuers: IssuerRegistry,
+}
+
+impl GiftCodec {
+    pub fn from_settings(settings: &Settings) -> Result<Self, SetupError> {
+        let cipher = GiftCipher::from_settings(settings)?;
+        let encrypt = settings.parse("GIFT_ENCRYPT")?.unwrap_or(false);
+        if encrypt && cipher.is_none() {
+            return Err(ConfigError::Missing("GIFT_ENCRYPTION_KEY".to_string()).into());
+        }
+        Ok(Self {
+            keys: KeyRing::from_settings(settings)?,
+            cipher,
+            encrypt,
+            compress: settings.parse("GIFT_COMPRESS")?.unwrap_or(false),
+            issuers: IssuerRegistry::from_settings(settings)?,
+        })
+    }
+
+    /// Wrap `contents` into a gift token valid for `lifetime` seconds, as `/16/wrap` does
+    /// but without a refresh token to go with it
+    pub fn wrap(&self, contents: Value, lifetime: i64) -> Result<String, TokenError> {
+        let now = chrono::Utc::now().timestamp();
+        let (contents, contents_zip) = self.compress_contents(contents);
+        let claims = Claim {
+            contents,
+            contents_zip,
+            exp: (now + lifetime) as usize,
+            iat: Some(now as usize),
+            jti: Some(uuid::Uuid::new_v4()),
+            fam: None,
+            token_use: None,
+        };
+        self.seal(&claims)
+    }
+
+    /// Sign a token granting `roles` to `subject` for `lifetime` seconds. Unlike a gift,
+    /// the roles are top-level claims, so the server's auth middleware honours them.
+    pub fn grant(
+        &self,
+        subject: &str,
+        roles: &[String],
+        lifetime: i64,
+    ) -> Result<String, TokenError> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = json!({
+            "sub": subject,
+            "roles": roles,
+            "exp": now + lifetime,
+            "iat": now,
+            "jti": uuid::Uuid::new_v4(),
+        });
+        let token = self.keys.sign(&claims)?;
+        self.encrypt(token)
+    }
+
+    /// Verify a gift token, with any compressed contents inflated
+    pub fn open<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, TokenError> {
+        let jwt = match jwe::is_jwe(token) {
+            true => {
+                let cipher = self.cipher.as_ref().ok_or(JweError::NoKey)?;
+                cipher.decrypt(token)?
+            }
+            false => token.to_string(),
+        };
+        let TokenData { header, mut claims } = self.keys.verify::<Value>(&jwt)?;
+        if compression::is_deflated(&claims) {
+            let contents = claims["contents"]
+                .as_str()
+                .and_then(compression::inflate)
+                .ok_or(TokenError::Compression)?;
+            claims["contents"] = contents;
+            if let Some(claims) = claims.as_object_mut() {
+                claims.remove("contents_zip");
+            }
+        }
+        let claims = serde_json::from_value(claims).map_err(jsonwebtoken::errors::Error::from)?;
+        Ok(TokenData { header, claims })
+    }
+
+    /// Verify a token from one of the trusted issuers, as `/16/decode` does
+    pub fn decode_trusted<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, DecodeError> {
+        self.issuers.decode(token)
+    }
+
+    /// Compress gift contents when configured to, along with the `contents_zip` claim
+    /// that marks them as compressed
+    pub(crate) fn compress_contents(&self, contents: Value) -> (Value, Option<String>) {
+        match self.compress {
+            true => (
+                Value::String(compression::deflate(&contents)),
+                Some(compression::DEFLATE.to_string()),
+            ),
+            false => (contents, None),
+        }
+    }
+
+    /// Sign `claims`, encrypting the result when configured to
+    pub(crate) fn seal<T: Serialize>(&self, claims: &T) -> Result<String, TokenError> {
+        let token = self.keys.sign(claims)?;
+        self.encrypt(token)
+    }
+
+    /// Encrypt a signed token when configured to
+    fn encrypt(&self, token: String) -> Result<String, TokenError> {
+        match &self.cipher {
+            Some(cipher) if self.encrypt => Ok(cipher.encrypt(&token)?),
+            _ => Ok(token),
+        }
+    }
+
+    pub(crate) fn jwks(&self) -> JwkSet {
+        self.keys.jwks()
+    }
+}
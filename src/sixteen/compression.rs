@@ -0,0 +1,33 @@
+use std::io::{Read, Write};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde_json::Value;
+
+/// The `contents_zip` claim marking a token whose `contents` claim is DEFLATE compressed.
+/// It's a private claim rather than the `zip` header, which JWS doesn't define.
+pub(crate) const DEFLATE: &str = "DEF";
+
+/// Compress gift contents into a base64url string of raw DEFLATE data
+pub(crate) fn deflate(contents: &Value) -> String {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder
+        .write_all(&serde_json::to_vec(contents).unwrap())
+        .unwrap();
+    URL_SAFE_NO_PAD.encode(encoder.finish().unwrap())
+}
+
+/// Undo [`deflate`], returning `None` if the data isn't valid
+pub(crate) fn inflate(compressed: &str) -> Option<Value> {
+    let compressed = URL_SAFE_NO_PAD.decode(compressed).ok()?;
+    let mut json = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_end(&mut json)
+        .ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Whether a token's claims say its contents are compressed
+pub(crate) fn is_deflated(claims: &Value) -> bool {
+    claims["contents_zip"] == DEFLATE
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use axum::http::{header::COOKIE, HeaderMap};
+use cookie::{time::Duration, Cookie, SameSite};
+use tracing::warn;
+
+use crate::config::{ConfigError, Settings};
+
+const GIFT_COOKIE: &str = "gift";
+const REFRESH_COOKIE: &str = "gift_refresh";
+/// Refresh cookies are only ever needed by the refresh endpoint
+const REFRESH_PATH: &str = "/16/refresh";
+
+/// Leaves room for the name and attributes within the 4096 bytes browsers allow per cookie
+const DEFAULT_CHUNK_BYTES: usize = 3800;
+
+/// Attributes of the gift cookies handed out by `/16/wrap`.
+///
+/// Configured with:
+/// - `GIFT_COOKIE_HTTP_ONLY` and `GIFT_COOKIE_SECURE`: both default to `true`
+/// - `GIFT_COOKIE_SAME_SITE`: `strict`, `lax` or `none`, defaulting to `lax`
+/// - `GIFT_COOKIE_PATH`: defaults to `/`
+/// - `GIFT_COOKIE_CHUNK_BYTES`: the longest value a single cookie holds before the token is
+///   split across `gift.0`, `gift.1`…, defaulting to 3800
+///
+/// Refresh cookies share the attributes but are scoped to the refresh endpoint.
+#[derive(Clone, Debug)]
+pub(crate) struct CookieConfig {
+    http_only: bool,
+    secure: bool,
+    same_site: SameSite,
+    path: String,
+    chunk_bytes: usize,
+}
+
+impl CookieConfig {
+    pub(crate) fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let same_site = match settings.get("GIFT_COOKIE_SAME_SITE") {
+            None => SameSite::Lax,
+            Some(value) => match value.to_ascii_lowercase().as_str() {
+                "strict" => SameSite::Strict,
+                "lax" => SameSite::Lax,
+                "none" => SameSite::None,
+                _ => {
+                    return Err(ConfigError::Invalid {
+                        name: "GIFT_COOKIE_SAME_SITE".to_string(),
+                        value,
+                    })
+                }
+            },
+        };
+        let config = Self {
+            http_only: settings.parse("GIFT_COOKIE_HTTP_ONLY")?.unwrap_or(true),
+            secure: settings.parse("GIFT_COOKIE_SECURE")?.unwrap_or(true),
+            same_site,
+            path: settings
+                .get("GIFT_COOKIE_PATH")
+                .unwrap_or_else(|| "/".to_string()),
+            chunk_bytes: settings
+                .parse("GIFT_COOKIE_CHUNK_BYTES")?
+                .unwrap_or(DEFAULT_CHUNK_BYTES),
+        };
+        if config.chunk_bytes == 0 {
+            return Err(ConfigError::Invalid {
+                name: "GIFT_COOKIE_CHUNK_BYTES".to_string(),
+                value: "0".to_string(),
+            });
+        }
+        if config.same_site == SameSite::None && !config.secure {
+            warn!("Browsers reject SameSite=None gift cookies that aren't also Secure");
+        }
+        Ok(config)
+    }
+
+    /// Gift cookies holding `token`, expiring along with it after `max_age` seconds.
+    /// Any gift cookies the `request` carries that aren't replaced are expired.
+    pub(crate) fn issue(
+        &self,
+        token: &str,
+        max_age: i64,
+        request: &HeaderMap,
+    ) -> Vec<Cookie<'static>> {
+        self.issue_as(GIFT_COOKIE, &self.path, token, max_age, request)
+    }
+
+    /// Refresh cookies holding `token`, expiring along with it after `max_age` seconds
+    pub(crate) fn issue_refresh(
+        &self,
+        token: &str,
+        max_age: i64,
+        request: &HeaderMap,
+    ) -> Vec<Cookie<'static>> {
+        self.issue_as(REFRESH_COOKIE, REFRESH_PATH, token, max_age, request)
+    }
+
+    /// A cookie called `name` holding `token`, or chunks called `name.0`, `name.1`…
+    /// if it's too long. Cookies left over from a previous token that was split differently
+    /// are expired so they can't be mixed into the new one.
+    fn issue_as(
+        &self,
+        name: &str,
+        path: &str,
+        token: &str,
+        max_age: i64,
+        request: &HeaderMap,
+    ) -> Vec<Cookie<'static>> {
+        let mut cookies = Vec::new();
+        if token.len() <= self.chunk_bytes {
+            cookies.push(self.build(name.to_string(), token.to_string(), path, max_age));
+        } else {
+            // Tokens are base64url and dots, so any byte boundary is a char boundary
+            for (index, chunk) in token.as_bytes().chunks(self.chunk_bytes).enumerate() {
+                let chunk = String::from_utf8_lossy(chunk).into_owned();
+                cookies.push(self.build(format!("{name}.{index}"), chunk, path, max_age));
+            }
+        }
+
+        let mut stale: Vec<String> = request_cookies(request)
+            .into_keys()
+            .filter(|existing| is_part_of(existing, name))
+            .filter(|existing| !cookies.iter().any(|cookie| cookie.name() == existing))
+            .collect();
+        // Refresh cookies aren't sent to `/16/wrap`, so leftover chunks can go unseen.
+        // Expiring the chunk after the last one stops any that remain being read as part
+        // of the new token, and `/16/refresh` expires the rest once it can see them.
+        if cookies.len() > 1 {
+            let next = format!("{name}.{}", cookies.len());
+            if !stale.contains(&next) {
+                stale.push(next);
+            }
+        }
+        stale.sort();
+        for existing in stale {
+            cookies.push(self.build(existing, String::new(), path, 0));
+        }
+        cookies
+    }
+
+    /// Expire every refresh cookie the `request` carries, for when its token is no use
+    pub(crate) fn expire_refresh(&self, request: &HeaderMap) -> Vec<Cookie<'static>> {
+        let mut names: Vec<String> = request_cookies(request)
+            .into_keys()
+            .filter(|existing| is_part_of(existing, REFRESH_COOKIE))
+            .collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| self.build(name, String::new(), REFRESH_PATH, 0))
+            .collect()
+    }
+
+    fn build(&self, name: String, value: String, path: &str, max_age: i64) -> Cookie<'static> {
+        Cookie::build((name, value))
+            .http_only(self.http_only)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .path(path.to_string())
+            .max_age(Duration::seconds(max_age))
+            .build()
+    }
+}
+
+pub(crate) fn gift_cookie(headers: &HeaderMap) -> Option<String> {
+    find_cookie(headers, GIFT_COOKIE)
+}
+
+pub(crate) fn refresh_cookie(headers: &HeaderMap) -> Option<String> {
+    find_cookie(headers, REFRESH_COOKIE)
+}
+
+/// Whether `cookie` is the cookie called `name` or one of its chunks
+fn is_part_of(cookie: &str, name: &str) -> bool {
+    match cookie.strip_prefix(name) {
+        Some("") => true,
+        Some(suffix) => suffix
+            .strip_prefix('.')
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    }
+}
+
+/// Every cookie across all of a request's `Cookie` headers.
+/// Malformed pairs are skipped rather than spoiling the rest of the header, and when a
+/// name repeats the first one wins, since browsers send cookies with longer paths first.
+fn request_cookies(headers: &HeaderMap) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for cookie in headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(Cookie::split_parse)
+        .filter_map(Result::ok)
+    {
+        cookies
+            .entry(cookie.name().to_string())
+            .or_insert_with(|| cookie.value().to_string());
+    }
+    cookies
+}
+
+/// Find a cookie by name, reassembling it from `name.0`, `name.1`… if it was split up
+fn find_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    let mut cookies = request_cookies(headers);
+    if let Some(value) = cookies.remove(name) {
+        return Some(value);
+    }
+    let chunks: Vec<String> = (0..)
+        .map_while(|index| cookies.remove(&format!("{name}.{index}")))
+        .collect();
+    match chunks.is_empty() {
+        true => None,
+        false => Some(chunks.concat()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_cookie_with_a_name_wins() {
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, "gift=current; gift.0=stale".parse().unwrap());
+        headers.append(COOKIE, "gift=old; other=1".parse().unwrap());
+        assert_eq!(find_cookie(&headers, "gift").as_deref(), Some("current"));
+
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, "gift.0=ab; gift.1=cd".parse().unwrap());
+        headers.append(COOKIE, "gift.0=xx; gift.1=yy".parse().unwrap());
+        assert_eq!(find_cookie(&headers, "gift").as_deref(), Some("abcd"));
+    }
+}
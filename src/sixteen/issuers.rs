@@ -0,0 +1,335 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header, errors::ErrorKind, jwk::JwkSet, Algorithm, DecodingKey, TokenData,
+    Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use thiserror::Error;
+use tracing::info;
+
+use crate::config::Settings;
+
+const SANTA_KEY: &str = include_str!("../../assets/day16_santa_public_key.pem");
+
+#[derive(Debug, Error)]
+pub enum IssuerError {
+    #[error("Couldn't read {path}: {source}")]
+    Unreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid issuer config {path}: {source}")]
+    InvalidConfig {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("Invalid JWKS {path}: {source}")]
+    InvalidJwks {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{0} doesn't hold an RSA, EC or Ed25519 public key")]
+    UnsupportedKey(PathBuf),
+    #[error("Issuer {0} has no keys")]
+    NoKeys(String),
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("No trusted issuer matches the token (iss: {0:?})")]
+    UntrustedIssuer(Option<String>),
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+
+impl DecodeError {
+    /// A stable name for the kind of failure, for clients to match on
+    pub fn class(&self) -> &'static str {
+        let DecodeError::Jwt(e) = self else {
+            return "untrusted_issuer";
+        };
+        match e.kind() {
+            ErrorKind::Base64(_) => "bad_base64",
+            ErrorKind::InvalidAlgorithm
+            | ErrorKind::InvalidAlgorithmName
+            | ErrorKind::MissingAlgorithm => "unsupported_algorithm",
+            ErrorKind::InvalidSignature => "bad_signature",
+            ErrorKind::ExpiredSignature => "expired",
+            ErrorKind::ImmatureSignature => "not_yet_valid",
+            ErrorKind::InvalidAudience => "wrong_audience",
+            ErrorKind::InvalidIssuer => "wrong_issuer",
+            ErrorKind::MissingRequiredClaim(_) => "missing_claim",
+            _ => "malformed",
+        }
+    }
+
+    /// Tokens that can't be tied to a trusted key are unauthorized, anything else is a bad request
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DecodeError::UntrustedIssuer(_) => StatusCode::UNAUTHORIZED,
+            DecodeError::Jwt(e) if *e.kind() == ErrorKind::InvalidSignature => {
+                StatusCode::UNAUTHORIZED
+            }
+            DecodeError::Jwt(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+fn default_algorithms() -> Vec<Algorithm> {
+    vec![Algorithm::RS256]
+}
+
+fn default_validate_exp() -> bool {
+    true
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+/// An issuer's `<name>.toml` file in the trusted issuers directory
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IssuerConfig {
+    /// The `iss` claim tokens must carry. Issuers without one vouch for any token their keys verify.
+    iss: Option<String>,
+    /// A PEM public key, or a JWKS if it ends in `.json`, relative to the directory
+    keys: PathBuf,
+    #[serde(default = "default_algorithms")]
+    algorithms: Vec<Algorithm>,
+    /// Claims that must be present, such as `exp`
+    #[serde(default)]
+    required_claims: Vec<String>,
+    #[serde(default = "default_validate_exp")]
+    validate_exp: bool,
+    #[serde(default)]
+    validate_nbf: bool,
+    /// Accepted `aud` values, left unchecked when not given
+    audience: Option<Vec<String>>,
+    /// Seconds of clock skew allowed when checking `exp` and `nbf`
+    #[serde(default = "default_leeway")]
+    leeway: u64,
+}
+
+struct IssuerKey {
+    kid: Option<String>,
+    key: DecodingKey,
+}
+
+struct Issuer {
+    name: String,
+    iss: Option<String>,
+    keys: Vec<IssuerKey>,
+    validation: Validation,
+}
+
+impl Issuer {
+    /// Santa's key, trusted when no issuers are configured: RS256 or RS512, with no required claims
+    fn santa() -> Self {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.algorithms = vec![Algorithm::RS256, Algorithm::RS512];
+        validation.required_spec_claims = HashSet::new();
+        validation.validate_aud = false;
+        Self {
+            name: "santa".to_string(),
+            iss: None,
+            keys: vec![IssuerKey {
+                kid: None,
+                key: DecodingKey::from_rsa_pem(SANTA_KEY.as_ref()).unwrap(),
+            }],
+            validation,
+        }
+    }
+
+    fn load(directory: &Path, path: &Path) -> Result<Self, IssuerError> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let config: IssuerConfig =
+            toml::from_str(&read(path)?).map_err(|source| IssuerError::InvalidConfig {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        let keys_path = directory.join(&config.keys);
+        let keys = match keys_path
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("json") => {
+                let jwks: JwkSet = serde_json::from_str(&read(&keys_path)?).map_err(|source| {
+                    IssuerError::InvalidJwks {
+                        path: keys_path.clone(),
+                        source,
+                    }
+                })?;
+                jwks.keys
+                    .iter()
+                    .map(|jwk| {
+                        Ok(IssuerKey {
+                            kid: jwk.common.key_id.clone(),
+                            key: DecodingKey::from_jwk(jwk)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, IssuerError>>()?
+            }
+            _ => {
+                let pem = read(&keys_path)?;
+                let key = DecodingKey::from_rsa_pem(pem.as_bytes())
+                    .or_else(|_| DecodingKey::from_ec_pem(pem.as_bytes()))
+                    .or_else(|_| DecodingKey::from_ed_pem(pem.as_bytes()))
+                    .map_err(|_| IssuerError::UnsupportedKey(keys_path))?;
+                vec![IssuerKey { kid: None, key }]
+            }
+        };
+        if keys.is_empty() || config.algorithms.is_empty() {
+            return Err(IssuerError::NoKeys(name));
+        }
+
+        let mut validation = Validation::new(config.algorithms[0]);
+        validation.algorithms = config.algorithms;
+        validation.required_spec_claims = config.required_claims.into_iter().collect();
+        validation.validate_exp = config.validate_exp;
+        validation.validate_nbf = config.validate_nbf;
+        validation.leeway = config.leeway;
+        match config.audience {
+            Some(audience) => validation.set_audience(&audience),
+            None => validation.validate_aud = false,
+        }
+        if let Some(iss) = &config.iss {
+            validation.set_issuer(&[iss]);
+        }
+
+        Ok(Self {
+            name,
+            iss: config.iss,
+            keys,
+            validation,
+        })
+    }
+}
+
+fn read(path: &Path) -> Result<String, IssuerError> {
+    std::fs::read_to_string(path).map_err(|source| IssuerError::Unreadable {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// The unverified claims used to pick an issuer
+#[derive(Deserialize)]
+struct Unverified {
+    iss: Option<String>,
+}
+
+/// The issuers whose tokens `/16/decode` accepts.
+///
+/// With `TRUSTED_ISSUERS_DIR` set, each `<name>.toml` file in that directory configures an issuer,
+/// otherwise only Santa's key is trusted.
+pub(crate) struct IssuerRegistry {
+    issuers: Vec<Issuer>,
+}
+
+impl IssuerRegistry {
+    pub(crate) fn from_settings(settings: &Settings) -> Result<Self, IssuerError> {
+        let Some(directory) = settings.get("TRUSTED_ISSUERS_DIR") else {
+            return Ok(Self {
+                issuers: vec![Issuer::santa()],
+            });
+        };
+        let directory = PathBuf::from(directory);
+        let entries = std::fs::read_dir(&directory).map_err(|source| IssuerError::Unreadable {
+            path: directory.clone(),
+            source,
+        })?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry
+                .map_err(|source| IssuerError::Unreadable {
+                    path: directory.clone(),
+                    source,
+                })?
+                .path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "toml")
+            {
+                paths.push(path);
+            }
+        }
+        // Issuers are tried in a stable order
+        paths.sort();
+
+        let issuers = paths
+            .iter()
+            .map(|path| Issuer::load(&directory, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        for issuer in &issuers {
+            info!(
+                "Trusting issuer {} ({} keys, iss {:?})",
+                issuer.name,
+                issuer.keys.len(),
+                issuer.iss
+            );
+        }
+        Ok(Self { issuers })
+    }
+
+    /// Verify a token against the issuers matching its `iss`, and their keys matching its `kid`
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, DecodeError> {
+        let header = decode_header(token)?;
+        let iss = token
+            .split('.')
+            .nth(1)
+            .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+            .and_then(|payload| serde_json::from_slice::<Unverified>(&payload).ok())
+            .and_then(|claims| claims.iss);
+
+        let issuers = self.issuers.iter().filter(|issuer| match &issuer.iss {
+            Some(expected) => iss.as_ref() == Some(expected),
+            None => true,
+        });
+        // The first key that didn't fit the token, for when none does
+        let mut mismatch = None;
+        for issuer in issuers {
+            let keys = issuer
+                .keys
+                .iter()
+                .filter(|key| match (&key.kid, &header.kid) {
+                    (Some(kid), Some(wanted)) => kid == wanted,
+                    _ => true,
+                });
+            for key in keys {
+                match decode(token, &key.key, &issuer.validation) {
+                    Ok(token) => return Ok(token),
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            ErrorKind::InvalidSignature | ErrorKind::InvalidAlgorithm
+                        ) =>
+                    {
+                        mismatch.get_or_insert(e);
+                    }
+                    // The signature checked out, so this is the real reason the token is rejected
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        match mismatch {
+            Some(e) => Err(e.into()),
+            None => Err(DecodeError::UntrustedIssuer(iss)),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::config::{ConfigError, Settings};
+
+/// Length of the AES-GCM authentication tag appended to the ciphertext
+const TAG_LENGTH: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum JweError {
+    #[error("Token isn't a compact JWE")]
+    Malformed,
+    #[error("Only `dir` + `A256GCM` encryption is supported")]
+    UnsupportedAlgorithm,
+    #[error("Received an encrypted token, but no encryption key is configured")]
+    NoKey,
+    #[error("Token couldn't be encrypted")]
+    Encryption,
+    #[error("Token couldn't be decrypted")]
+    Decryption,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct JweHeader {
+    alg: String,
+    enc: String,
+    /// Always `JWT`, since the payload is itself a signed gift token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cty: Option<String>,
+}
+
+/// Encrypts signed gift tokens into compact JWEs using direct `A256GCM` encryption,
+/// so only holders of the key can read the gift.
+///
+/// Configured with `GIFT_ENCRYPTION_KEY`, 32 base64 encoded bytes.
+pub(crate) struct GiftCipher {
+    cipher: Aes256Gcm,
+}
+
+impl GiftCipher {
+    pub(crate) fn from_settings(settings: &Settings) -> Result<Option<Self>, ConfigError> {
+        let Some(encoded) = settings.get("GIFT_ENCRYPTION_KEY") else {
+            return Ok(None);
+        };
+        let key = URL_SAFE_NO_PAD
+            .decode(&encoded)
+            .or_else(|_| STANDARD.decode(&encoded))
+            .ok()
+            .filter(|key| key.len() == 32);
+        match key {
+            Some(key) => Ok(Some(Self {
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+            })),
+            None => Err(ConfigError::Invalid {
+                name: "GIFT_ENCRYPTION_KEY".to_string(),
+                value: "<redacted>".to_string(),
+            }),
+        }
+    }
+
+    /// Wrap a signed token in a JWE
+    pub(crate) fn encrypt(&self, jwt: &str) -> Result<String, JweError> {
+        let header = JweHeader {
+            alg: "dir".to_string(),
+            enc: "A256GCM".to_string(),
+            cty: Some("JWT".to_string()),
+        };
+        let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).unwrap());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut ciphertext = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: jwt.as_bytes(),
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| JweError::Encryption)?;
+        let tag = ciphertext.split_off(ciphertext.len() - TAG_LENGTH);
+        // `dir` encryption has no encrypted key, leaving the second segment empty
+        Ok(format!(
+            "{header}..{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(nonce),
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    /// Recover the signed token from a JWE, which still needs verifying
+    pub(crate) fn decrypt(&self, jwe: &str) -> Result<String, JweError> {
+        let [header, key, nonce, ciphertext, tag] = jwe
+            .split('.')
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| JweError::Malformed)?;
+        let decode = |segment: &str| {
+            URL_SAFE_NO_PAD
+                .decode(segment)
+                .map_err(|_| JweError::Malformed)
+        };
+
+        let parsed: JweHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| JweError::Malformed)?;
+        if parsed.alg != "dir" || parsed.enc != "A256GCM" || !key.is_empty() {
+            return Err(JweError::UnsupportedAlgorithm);
+        }
+        let nonce = decode(nonce)?;
+        if nonce.len() != 12 {
+            return Err(JweError::Malformed);
+        }
+        let mut message = decode(ciphertext)?;
+        message.extend(decode(tag)?);
+
+        let plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &message,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| JweError::Decryption)?;
+        String::from_utf8(plaintext).map_err(|_| JweError::Malformed)
+    }
+}
+
+/// Whether a token is a compact JWE (five segments) rather than a JWS (three)
+pub(crate) fn is_jwe(token: &str) -> bool {
+    token.split('.').count() == 5
+}
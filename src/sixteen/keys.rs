@@ -0,0 +1,247 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{pkcs8::DecodePrivateKey, SigningKey};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use rand::Rng;
+use rsa::{pkcs1::DecodeRsaPrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+use tracing::{info, warn};
+
+use super::jwe::JweError;
+use crate::config::Settings;
+
+#[derive(Debug, Error)]
+pub enum KeyError {
+    #[error("Signing key entries look like `kid=secret`, got `{0}`")]
+    MalformedEntry(String),
+    #[error("The active signing key `{0}` isn't configured or has been retired")]
+    InactiveKey(String),
+    #[error("Couldn't read signing key file {path}: {source}")]
+    Unreadable {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("{0} doesn't hold an RSA or Ed25519 private key")]
+    UnsupportedKey(String),
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("Token was signed with an unknown or retired key: {0}")]
+    UnknownKey(String),
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+    #[error(transparent)]
+    Encryption(#[from] JweError),
+    #[error("Compressed contents couldn't be inflated")]
+    Compression,
+}
+
+/// A key gift tokens are signed and verified with, identified by the `kid` header
+struct GiftKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// The public half of an asymmetric key, as published in the JWKS
+    public: Option<Jwk>,
+}
+
+impl GiftKey {
+    fn from_secret(kid: &str, secret: &[u8]) -> Self {
+        Self {
+            kid: kid.to_string(),
+            algorithm: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+            public: None,
+        }
+    }
+
+    /// Load an RSA (PKCS#1 or PKCS#8) or Ed25519 (PKCS#8) private key from a PEM file,
+    /// signing with RS256 or EdDSA respectively
+    fn from_pem_file(kid: &str, path: &str) -> Result<Self, KeyError> {
+        let pem = std::fs::read_to_string(path).map_err(|source| KeyError::Unreadable {
+            path: path.to_string(),
+            source,
+        })?;
+
+        let rsa = RsaPrivateKey::from_pkcs1_pem(&pem)
+            .ok()
+            .or_else(|| rsa::pkcs8::DecodePrivateKey::from_pkcs8_pem(&pem).ok());
+        if let Some(private) = rsa {
+            let n = URL_SAFE_NO_PAD.encode(private.n().to_bytes_be());
+            let e = URL_SAFE_NO_PAD.encode(private.e().to_bytes_be());
+            return Ok(Self {
+                kid: kid.to_string(),
+                algorithm: Algorithm::RS256,
+                encoding: EncodingKey::from_rsa_pem(pem.as_bytes())?,
+                decoding: DecodingKey::from_rsa_components(&n, &e)?,
+                public: Some(public_jwk(
+                    kid,
+                    KeyAlgorithm::RS256,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n,
+                        e,
+                    }),
+                )),
+            });
+        }
+
+        if let Ok(private) = SigningKey::from_pkcs8_pem(&pem) {
+            let x = URL_SAFE_NO_PAD.encode(private.verifying_key().as_bytes());
+            return Ok(Self {
+                kid: kid.to_string(),
+                algorithm: Algorithm::EdDSA,
+                encoding: EncodingKey::from_ed_pem(pem.as_bytes())?,
+                decoding: DecodingKey::from_ed_components(&x)?,
+                public: Some(public_jwk(
+                    kid,
+                    KeyAlgorithm::EdDSA,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x,
+                    }),
+                )),
+            });
+        }
+
+        Err(KeyError::UnsupportedKey(path.to_string()))
+    }
+
+    fn validation(&self) -> Validation {
+        Validation::new(self.algorithm)
+    }
+}
+
+fn public_jwk(kid: &str, algorithm: KeyAlgorithm, parameters: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    }
+}
+
+/// The keys accepted for gift tokens, one of which signs new tokens.
+///
+/// Configured with:
+/// - `GIFT_SIGNING_KEYS`: comma separated `kid=secret` pairs, signing with HS256
+/// - `GIFT_SIGNING_KEY_FILES`: comma separated `kid=path` pairs naming RSA or Ed25519
+///   private keys in PEM files, signing with RS256 or EdDSA
+/// - `GIFT_ACTIVE_KEY`: the kid that signs new tokens, defaulting to the first key
+/// - `GIFT_RETIRED_KEYS`: comma separated kids whose tokens are no longer accepted
+///
+/// Rotating means adding a new key and making it active, then retiring the old one
+/// once the tokens it signed have expired.
+pub(crate) struct KeyRing {
+    keys: Vec<GiftKey>,
+    active: usize,
+}
+
+impl KeyRing {
+    pub(crate) fn from_settings(settings: &Settings) -> Result<Self, KeyError> {
+        let retired = settings.list("GIFT_RETIRED_KEYS");
+        let mut keys = Vec::new();
+        for (setting, from_file) in [
+            ("GIFT_SIGNING_KEYS", false),
+            ("GIFT_SIGNING_KEY_FILES", true),
+        ] {
+            for entry in settings.list(setting) {
+                let Some((kid, value)) = entry.split_once('=') else {
+                    return Err(KeyError::MalformedEntry(entry));
+                };
+                if retired.iter().any(|retired| retired == kid) {
+                    info!("Ignoring retired gift signing key {kid}");
+                    continue;
+                }
+                keys.push(match from_file {
+                    true => GiftKey::from_pem_file(kid, value)?,
+                    false => GiftKey::from_secret(kid, value.as_bytes()),
+                });
+            }
+        }
+
+        if keys.is_empty() {
+            warn!("No GIFT_SIGNING_KEYS configured, gift cookies won't survive a restart");
+            let secret = rand::thread_rng().gen::<[u8; 32]>();
+            keys.push(GiftKey::from_secret("ephemeral", &secret));
+        }
+
+        let active = match settings.get("GIFT_ACTIVE_KEY") {
+            Some(active) => keys
+                .iter()
+                .position(|key| key.kid == active)
+                .ok_or(KeyError::InactiveKey(active))?,
+            None => 0,
+        };
+        info!(
+            "Signing gift tokens with {:?} key {}",
+            keys[active].algorithm, keys[active].kid
+        );
+        Ok(Self { keys, active })
+    }
+
+    /// Sign `claims` with the active key
+    pub(crate) fn sign<T: Serialize>(&self, claims: &T) -> Result<String, TokenError> {
+        let key = &self.keys[self.active];
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::new(key.algorithm)
+        };
+        Ok(encode(&header, claims, &key.encoding)?)
+    }
+
+    /// Verify a token against the key named by its `kid`, which must also match its algorithm.
+    /// Tokens from before keys had ids are tried against every accepted key.
+    pub(crate) fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, TokenError> {
+        match decode_header(token)?.kid {
+            Some(kid) => {
+                let key = self
+                    .keys
+                    .iter()
+                    .find(|key| key.kid == kid)
+                    .ok_or(TokenError::UnknownKey(kid))?;
+                Ok(decode(token, &key.decoding, &key.validation())?)
+            }
+            None => {
+                let mut last_error = None;
+                for key in &self.keys {
+                    match decode(token, &key.decoding, &key.validation()) {
+                        Ok(token) => return Ok(token),
+                        Err(e) => last_error = Some(e),
+                    }
+                }
+                Err(last_error.expect("the key ring is never empty").into())
+            }
+        }
+    }
+
+    /// The public keys of every accepted asymmetric key, so others can verify gift tokens
+    pub(crate) fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keys
+                .iter()
+                .filter_map(|key| key.public.clone())
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Refresh tokens that have been issued, and whether they've been used yet.
+/// Each refresh token may be exchanged once; seeing one again means it leaked.
+#[derive(Clone, Debug)]
+pub(crate) struct RefreshTokens {
+    pool: PgPool,
+}
+
+impl RefreshTokens {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub(crate) async fn issue(
+        &self,
+        jti: Uuid,
+        family: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO refresh_tokens (jti, family, expires_at) VALUES ($1, $2, $3)")
+            .bind(jti)
+            .bind(family)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark a refresh token as used, returning false if it already was or was never issued
+    pub(crate) async fn consume(&self, jti: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE refresh_tokens SET used_at = CURRENT_TIMESTAMP WHERE jti = $1 AND used_at IS NULL",
+        )
+        .bind(jti)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Whether a refresh token was issued and can still be exchanged
+    pub(crate) async fn is_unused(&self, jti: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM refresh_tokens WHERE jti = $1 AND used_at IS NULL)",
+        )
+        .bind(jti)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    /// Forget refresh tokens that have since expired, returning how many were removed
+    pub(crate) async fn purge_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
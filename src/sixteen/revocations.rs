@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Ids of gift tokens, or whole families of them, that were revoked before they expired.
+/// Entries are only needed until the token expires, after which it's rejected anyway.
+#[derive(Clone, Debug)]
+pub(crate) struct RevocationList {
+    pool: PgPool,
+}
+
+impl RevocationList {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub(crate) async fn revoke(
+        &self,
+        jti: Uuid,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO revoked_tokens (jti, expires_at) VALUES ($1, $2) ON CONFLICT (jti) DO NOTHING",
+        )
+        .bind(jti)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Whether any of `ids`, such as a token's id and its family, has been revoked
+    pub(crate) async fn is_revoked(&self, ids: &[Uuid]) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = ANY($1))")
+            .bind(ids)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// Forget revoked tokens that have since expired, returning how many were removed
+    pub(crate) async fn purge_expired(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < CURRENT_TIMESTAMP")
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -1,15 +1,33 @@
 use axum::{
-    extract::{Path, State},
-    http::{Response, StatusCode},
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Json, Path, Query, State,
+    },
+    http::{header::CONTENT_TYPE, HeaderMap, Response, StatusCode},
+    response::IntoResponse,
 };
+use chrono::{DateTime, Utc};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::{
-    fmt::Display,
-    sync::{Arc, Mutex},
-};
+use std::{fmt::Display, sync::Arc};
 use thiserror::Error;
-use tracing::info;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::sixteen::{AuthError, Principal, ADMIN_ROLE};
+
+mod clock;
+mod store;
+mod tournament;
+
+use clock::{Clock, ClockKind, TimeControl};
+use store::GameStatus;
+pub(super) use store::GameStore;
+pub(super) use tournament::tournament;
+
+const PLAY_PAGE: &str = include_str!("../assets/12.html");
 
 #[derive(Debug, Error)]
 pub(super) enum Error {
@@ -17,6 +35,44 @@ pub(super) enum Error {
     InvalidColumn(usize),
     #[error("Column is full: {0}")]
     ColumnFull(usize),
+    #[error("Column is empty: {0}")]
+    ColumnEmpty(usize),
+    #[error("The bottom of column {0} belongs to the other team")]
+    NotYourItem(usize),
+    #[error("Pop Out is not enabled for this game")]
+    PopOutDisabled,
+    #[error("The game is already over")]
+    GameOver,
+    #[error("It is {0}'s turn")]
+    NotYourTurn(SquareState),
+}
+
+/// The rule set a game is played under
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Variant {
+    /// Items can only be dropped into a column
+    #[default]
+    Classic,
+    /// Teams may also remove one of their own items from the bottom of a column
+    PopOut,
+}
+
+impl Variant {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Classic => "classic",
+            Variant::PopOut => "popout",
+        }
+    }
+
+    fn parse(variant: &str) -> Option<Self> {
+        match variant {
+            "classic" => Some(Variant::Classic),
+            "popout" => Some(Variant::PopOut),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
@@ -27,6 +83,34 @@ pub(super) enum SquareState {
     Milk,
 }
 
+impl SquareState {
+    /// The team playing against this one
+    fn opponent(&self) -> Self {
+        match self {
+            SquareState::Cookie => SquareState::Milk,
+            SquareState::Milk => SquareState::Cookie,
+            SquareState::Empty => SquareState::Empty,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SquareState::Empty => "empty",
+            SquareState::Cookie => "cookie",
+            SquareState::Milk => "milk",
+        }
+    }
+
+    fn parse(team: &str) -> Option<Self> {
+        match team {
+            "empty" => Some(SquareState::Empty),
+            "cookie" => Some(SquareState::Cookie),
+            "milk" => Some(SquareState::Milk),
+            _ => None,
+        }
+    }
+}
+
 impl Display for SquareState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -41,12 +125,97 @@ impl Display for SquareState {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum GameState {
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum GameState {
     Ongoing,
     Cookie,
     Milk,
     Draw,
+    /// Cookie ran out of time in a timed game, so milk wins
+    CookieOutOfTime,
+    /// Milk ran out of time in a timed game, so cookie wins
+    MilkOutOfTime,
+}
+
+impl GameState {
+    /// The team that won, if the game has a winner
+    fn winner(&self) -> Option<SquareState> {
+        match self {
+            GameState::Cookie | GameState::MilkOutOfTime => Some(SquareState::Cookie),
+            GameState::Milk | GameState::CookieOutOfTime => Some(SquareState::Milk),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            GameState::Ongoing => "ongoing",
+            GameState::Cookie => "cookie",
+            GameState::Milk => "milk",
+            GameState::Draw => "draw",
+            GameState::CookieOutOfTime => "cookie_out_of_time",
+            GameState::MilkOutOfTime => "milk_out_of_time",
+        }
+    }
+
+    fn parse(state: &str) -> Option<Self> {
+        match state {
+            "ongoing" => Some(GameState::Ongoing),
+            "cookie" => Some(GameState::Cookie),
+            "milk" => Some(GameState::Milk),
+            "draw" => Some(GameState::Draw),
+            "cookie_out_of_time" => Some(GameState::CookieOutOfTime),
+            "milk_out_of_time" => Some(GameState::MilkOutOfTime),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum MoveKind {
+    /// Drop an item on top of a column
+    Drop,
+    /// Take the team's own item out of the bottom of a column
+    Pop,
+}
+
+impl MoveKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MoveKind::Drop => "drop",
+            MoveKind::Pop => "pop",
+        }
+    }
+
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "drop" => Some(MoveKind::Drop),
+            "pop" => Some(MoveKind::Pop),
+            _ => None,
+        }
+    }
+}
+
+/// A single move, with the 1-indexed column used by the API
+#[derive(Clone, Copy, Debug, Serialize)]
+pub(super) struct Move {
+    team: SquareState,
+    column: usize,
+    kind: MoveKind,
+    played_at: DateTime<Utc>,
+}
+
+impl Move {
+    fn new(team: SquareState, column: usize, kind: MoveKind) -> Self {
+        Self {
+            team,
+            column,
+            kind,
+            played_at: Utc::now(),
+        }
+    }
 }
 
 const BOARD_SIZE: usize = 4;
@@ -63,14 +232,24 @@ const BOARD_AREA: usize = BOARD_SIZE * BOARD_SIZE;
 /// |---+---+---+---|
 /// |0,0|1,0|2,0|3,0|
 /// |---+---+---+---|
+#[derive(Clone)]
 pub(super) struct Board {
     squares: [SquareState; BOARD_AREA],
+    variant: Variant,
+    /// The team that made the latest move, which wins if it completed lines for both teams
+    last_mover: Option<SquareState>,
 }
 
 impl Board {
     pub fn new() -> Self {
+        Self::with_variant(Variant::Classic)
+    }
+
+    pub fn with_variant(variant: Variant) -> Self {
         Self {
             squares: [SquareState::Empty; 16],
+            variant,
+            last_mover: None,
         }
     }
 
@@ -100,9 +279,30 @@ impl Board {
 
     fn reset(&mut self) {
         self.squares = [SquareState::Empty; 16];
+        self.last_mover = None;
+    }
+
+    fn apply(&mut self, played: &Move) -> Result<(), Error> {
+        let column_idx = played
+            .column
+            .checked_sub(1)
+            .ok_or(Error::InvalidColumn(played.column))?;
+        match played.kind {
+            MoveKind::Drop => self.place_item(column_idx, played.team),
+            MoveKind::Pop => self.pop_item(column_idx, played.team),
+        }
     }
 
     fn place_item(&mut self, column_idx: usize, item: SquareState) -> Result<(), Error> {
+        let row = self.drop_item(column_idx, item)?;
+        info!("Placed {:?} in column {}, row {}", item, column_idx, row);
+        info!("Square value: {}", self.cell(row, column_idx));
+        Ok(())
+    }
+
+    /// Place `item` in the lowest empty square of the column, returning the row it landed in.
+    /// Unlike `place_item` this doesn't log, so bots can search through many positions.
+    fn drop_item(&mut self, column_idx: usize, item: SquareState) -> Result<usize, Error> {
         if column_idx >= BOARD_SIZE {
             return Err(Error::InvalidColumn(column_idx));
         }
@@ -110,15 +310,54 @@ impl Board {
             let square = self.cell_mut(row, column_idx);
             if *square == SquareState::Empty {
                 *square = item;
-                info!("Placed {:?} in column {}, row {}", item, column_idx, row);
-                info!("Square value: {square}");
-                return Ok(());
+                self.last_mover = Some(item);
+                return Ok(row);
             }
         }
         Err(Error::ColumnFull(column_idx))
     }
 
+    /// Remove `team`'s item from the bottom of a column, shifting the rest of the column down
+    fn pop_item(&mut self, column_idx: usize, team: SquareState) -> Result<(), Error> {
+        if column_idx >= BOARD_SIZE {
+            return Err(Error::InvalidColumn(column_idx));
+        }
+        if self.variant != Variant::PopOut {
+            return Err(Error::PopOutDisabled);
+        }
+        match *self.cell(0, column_idx) {
+            SquareState::Empty => return Err(Error::ColumnEmpty(column_idx)),
+            bottom if bottom != team => return Err(Error::NotYourItem(column_idx)),
+            _ => (),
+        }
+        for row in 0..BOARD_SIZE - 1 {
+            let above = *self.cell(row + 1, column_idx);
+            *self.cell_mut(row, column_idx) = above;
+        }
+        *self.cell_mut(BOARD_SIZE - 1, column_idx) = SquareState::Empty;
+        self.last_mover = Some(team);
+        info!("Popped {:?} from column {}", team, column_idx);
+        Ok(())
+    }
+
+    /// The team whose turn it is, with cookie moving first
+    fn next_team(&self) -> SquareState {
+        match self.last_mover {
+            Some(team) => team.opponent(),
+            None => SquareState::Cookie,
+        }
+    }
+
+    /// The 0-indexed columns that still have room for another item
+    fn open_columns(&self) -> Vec<usize> {
+        (0..BOARD_SIZE)
+            .filter(|&column| *self.cell(BOARD_SIZE - 1, column) == SquareState::Empty)
+            .collect()
+    }
+
     fn game_over(&self) -> GameState {
+        // Every completed line, in the order they're found
+        let mut lines = Vec::new();
         // Check for horizontal wins
         for row in 0..BOARD_SIZE {
             for column in 0..BOARD_SIZE - 3 {
@@ -128,11 +367,7 @@ impl Board {
                     && square == self.cell(row, column + 2)
                     && square == self.cell(row, column + 3)
                 {
-                    match square {
-                        SquareState::Cookie => return GameState::Cookie,
-                        SquareState::Milk => return GameState::Milk,
-                        _ => unreachable!("We already made sure the square wasn't empty"),
-                    };
+                    lines.push(*square);
                 }
             }
         }
@@ -145,11 +380,7 @@ impl Board {
                     && square == self.cell(row + 2, column)
                     && square == self.cell(row + 3, column)
                 {
-                    match square {
-                        SquareState::Cookie => return GameState::Cookie,
-                        SquareState::Milk => return GameState::Milk,
-                        _ => unreachable!("We already made sure the square wasn't empty"),
-                    };
+                    lines.push(*square);
                 }
             }
         }
@@ -162,11 +393,7 @@ impl Board {
                     && square == self.cell(row + 2, column + 2)
                     && square == self.cell(row + 3, column + 3)
                 {
-                    match square {
-                        SquareState::Cookie => return GameState::Cookie,
-                        SquareState::Milk => return GameState::Milk,
-                        _ => unreachable!("We already made sure the square wasn't empty"),
-                    };
+                    lines.push(*square);
                 }
             }
         }
@@ -178,19 +405,40 @@ impl Board {
                     && square == self.cell(row + 2, column - 2)
                     && square == self.cell(row + 3, column - 3)
                 {
-                    match square {
-                        SquareState::Cookie => return GameState::Cookie,
-                        SquareState::Milk => return GameState::Milk,
-                        _ => unreachable!("We already made sure the square wasn't empty"),
-                    };
+                    lines.push(*square);
                 }
             }
         }
+        let winner = match (
+            lines.contains(&SquareState::Cookie),
+            lines.contains(&SquareState::Milk),
+        ) {
+            (false, false) => None,
+            (true, false) => Some(SquareState::Cookie),
+            (false, true) => Some(SquareState::Milk),
+            // A pop can complete lines for both teams at once, in which case the team that
+            // made the move wins. Boards without a last move fall back to the first line found.
+            (true, true) => Some(self.last_mover.unwrap_or(lines[0])),
+        };
+        match winner {
+            Some(SquareState::Cookie) => return GameState::Cookie,
+            Some(SquareState::Milk) => return GameState::Milk,
+            _ => (),
+        }
         for square in self.squares.iter() {
             if *square == SquareState::Empty {
                 return GameState::Ongoing;
             }
         }
+        // A full Pop Out board plays on while the team to move has an item to pop
+        if self.variant == Variant::PopOut {
+            if let Some(last_mover) = self.last_mover {
+                let next = last_mover.opponent();
+                if (0..BOARD_SIZE).any(|column| *self.cell(0, column) == next) {
+                    return GameState::Ongoing;
+                }
+            }
+        }
         GameState::Draw
     }
 }
@@ -208,17 +456,17 @@ impl Display for Board {
         for _ in 0..6 {
             write!(f, "⬜")?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         let result = self.game_over();
         match result {
             GameState::Cookie => {
-                write!(f, "🍪 wins!\n")?;
+                writeln!(f, "🍪 wins!")?;
             }
             GameState::Milk => {
-                write!(f, "🥛 wins!\n")?;
+                writeln!(f, "🥛 wins!")?;
             }
             GameState::Draw => {
-                write!(f, "No winner.\n")?;
+                writeln!(f, "No winner.")?;
             }
             _ => (),
         }
@@ -226,72 +474,671 @@ impl Display for Board {
     }
 }
 
+impl Board {
+    /// Render the board as the HTML fragment swapped in by `/12/play`.
+    /// Clicking a column drops the next team's item into it.
+    fn to_html(&self, result: GameState, time_control: Option<TimeControl>) -> String {
+        let team = self.next_team();
+        let open = self.open_columns();
+        let mut html =
+            String::from("<div id=\"board\" class=\"board\">\n<div class=\"columns\">\n");
+        for column in 0..BOARD_SIZE {
+            let disabled = match result == GameState::Ongoing && open.contains(&column) {
+                true => "",
+                false => " disabled",
+            };
+            html.push_str(&format!(
+                "<button class=\"column\" hx-post=\"/12/place/{}/{}\" hx-target=\"#board\" hx-swap=\"outerHTML\"{disabled}>\n",
+                team.as_str(),
+                column + 1
+            ));
+            for row in (0..BOARD_SIZE).rev() {
+                html.push_str(&format!(
+                    "<div class=\"cell {}\"></div>\n",
+                    self.cell(row, column).as_str()
+                ));
+            }
+            html.push_str("</button>\n");
+        }
+        html.push_str("</div>\n");
+        if self.variant == Variant::PopOut && result == GameState::Ongoing {
+            html.push_str("<div class=\"pops\">\n");
+            for column in 0..BOARD_SIZE {
+                let disabled = match *self.cell(0, column) == team {
+                    true => "",
+                    false => " disabled",
+                };
+                html.push_str(&format!(
+                    "<button class=\"pop\" hx-post=\"/12/pop/{}/{}\" hx-target=\"#board\" hx-swap=\"outerHTML\"{disabled}>Pop</button>\n",
+                    team.as_str(),
+                    column + 1
+                ));
+            }
+            html.push_str("</div>\n");
+        }
+        let banner = match result {
+            GameState::Cookie => "<div class=\"banner winner\">🍪 wins!</div>".to_string(),
+            GameState::Milk => "<div class=\"banner winner\">🥛 wins!</div>".to_string(),
+            GameState::Draw => "<div class=\"banner winner\">No winner.</div>".to_string(),
+            GameState::CookieOutOfTime => {
+                "<div class=\"banner winner\">🍪 ran out of time. 🥛 wins!</div>".to_string()
+            }
+            GameState::MilkOutOfTime => {
+                "<div class=\"banner winner\">🥛 ran out of time. 🍪 wins!</div>".to_string()
+            }
+            GameState::Ongoing => format!("<div class=\"banner\">{team} to play</div>"),
+        };
+        html.push_str(&banner);
+        // Abandoning a game in progress or changing the rules takes an admin token, which
+        // the page doesn't have, so the button only appears once the game is over and
+        // starts another under the same rules
+        if result != GameState::Ongoing {
+            let clock = time_control
+                .map(|control| {
+                    format!(
+                        "&clock={}&seconds={}",
+                        control.kind.as_str(),
+                        control.seconds
+                    )
+                })
+                .unwrap_or_default();
+            html.push_str(&format!(
+                "\n<button class=\"reset\" hx-post=\"/12/reset?variant={}{clock}\" hx-target=\"#board\" hx-swap=\"outerHTML\">New game</button>",
+                self.variant.as_str()
+            ));
+        }
+        html.push_str("\n</div>");
+        html
+    }
+}
+
+/// Whether a request was made by htmx, which expects HTML fragments rather than text
+fn is_htmx(headers: &HeaderMap) -> bool {
+    headers.contains_key("HX-Request")
+}
+
+/// A snapshot of the game pushed to websocket subscribers whenever the board changes
+#[derive(Clone, Debug, Serialize)]
+pub(super) struct BoardUpdate {
+    board: String,
+    state: GameState,
+    variant: Variant,
+}
+
+/// Commands accepted from websocket clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Command {
+    Place {
+        team: SquareState,
+        column: usize,
+    },
+    Pop {
+        team: SquareState,
+        column: usize,
+    },
+    Reset {
+        #[serde(default)]
+        variant: Variant,
+        clock: Option<ClockKind>,
+        seconds: Option<u32>,
+    },
+}
+
 pub(super) struct AppState {
     board: Board,
     rng: rand::rngs::StdRng,
+    updates: broadcast::Sender<BoardUpdate>,
+    game: Uuid,
+    moves: Vec<Move>,
+    store: GameStore,
+    clock: Option<Clock>,
+    /// Wakes the flag watcher whenever the clock is armed or a turn ends
+    clock_changed: Arc<Notify>,
+    /// Teams with a player connected over the websocket
+    seats: Vec<SquareState>,
 }
 
 impl AppState {
-    pub fn construct() -> Arc<Mutex<Self>> {
-        Arc::new(Mutex::new(Self {
+    /// Resume the most recent unfinished game from the store, or start a fresh one
+    pub async fn restore(store: GameStore) -> Arc<Mutex<Self>> {
+        let (updates, _) = broadcast::channel(16);
+        let mut state = Self {
             board: Board::new(),
             rng: rand::SeedableRng::seed_from_u64(2024),
-        }))
+            updates,
+            game: Uuid::new_v4(),
+            moves: Vec::new(),
+            store,
+            clock: None,
+            clock_changed: Arc::new(Notify::new()),
+            seats: Vec::new(),
+        };
+        match state.store.latest_ongoing().await {
+            Ok(Some(stored)) => {
+                let game = stored.id;
+                info!("Resuming game {game} after {} moves", stored.moves.len());
+                state.game = game;
+                state.board = Board::with_variant(stored.variant);
+                state.clock = stored
+                    .time_control
+                    .map(|control| Clock::new(control, stored.created_at));
+                for played in stored.moves {
+                    if let Err(e) = state.board.apply(&played) {
+                        error!(
+                            "Skipping move {played:?} of game {game}, which can't be replayed: {e}"
+                        );
+                        continue;
+                    }
+                    if let Some(clock) = state.clock.as_mut() {
+                        clock.record_move(played.team, played.played_at);
+                    }
+                    state.moves.push(played);
+                }
+                if let Some(clock) = state.clock.as_mut() {
+                    clock.resume(Utc::now());
+                }
+                // The server may have stopped between storing the winning move and the result
+                let result = state.state();
+                if result != GameState::Ongoing {
+                    info!("Game {game} was already over, recording the result");
+                    if let Err(e) = state
+                        .store
+                        .finish(game, GameStatus::Finished, Some(result))
+                        .await
+                    {
+                        error!("Failed to store result of game {game}: {e}");
+                    }
+                }
+            }
+            Ok(None) => state.start_game().await,
+            Err(e) => {
+                error!("Failed to load ongoing games, starting a new one: {e}");
+                state.start_game().await;
+            }
+        }
+        Arc::new(Mutex::new(state))
+    }
+
+    /// End timed games as soon as a flag falls, so websocket watchers hear about it
+    /// without waiting for somebody to look at the board
+    pub fn spawn_flag_watch(state: &Arc<Mutex<Self>>) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let changed = state.lock().await.clock_changed.clone();
+            loop {
+                let flag_falls_in = state.lock().await.flag_falls_in();
+                match flag_falls_in {
+                    Some(wait) => tokio::select! {
+                        _ = tokio::time::sleep(wait) => state.lock().await.check_clock().await,
+                        _ = changed.notified() => (),
+                    },
+                    None => changed.notified().await,
+                }
+            }
+        });
+    }
+
+    /// How long the team to move has left, if an ongoing game is timed
+    fn flag_falls_in(&self) -> Option<std::time::Duration> {
+        if self.state() != GameState::Ongoing {
+            return None;
+        }
+        let remaining = self.clock?.remaining(self.board.next_team(), Utc::now());
+        Some(remaining.to_std().unwrap_or_default())
+    }
+
+    async fn start_game(&mut self) {
+        self.game = Uuid::new_v4();
+        self.moves.clear();
+        if let Err(e) = self
+            .store
+            .start(
+                self.game,
+                self.board.variant,
+                self.clock.map(|clock| clock.control),
+                BOARD_SIZE,
+                BOARD_SIZE,
+            )
+            .await
+        {
+            error!("Failed to store new game {}: {e}", self.game);
+        }
+    }
+
+    /// The state of the game, including a loss on time
+    fn state(&self) -> GameState {
+        match self.clock.and_then(|clock| clock.flagged) {
+            Some(SquareState::Cookie) => GameState::CookieOutOfTime,
+            Some(SquareState::Milk) => GameState::MilkOutOfTime,
+            _ => self.board.game_over(),
+        }
+    }
+
+    /// The board as text, followed by the result if a team ran out of time
+    fn render(&self) -> String {
+        let mut text = self.board.to_string();
+        match self.state() {
+            GameState::CookieOutOfTime => text.push_str("🍪 ran out of time. 🥛 wins!\n"),
+            GameState::MilkOutOfTime => text.push_str("🥛 ran out of time. 🍪 wins!\n"),
+            _ => (),
+        }
+        text
+    }
+
+    fn update(&self) -> BoardUpdate {
+        BoardUpdate {
+            board: self.render(),
+            state: self.state(),
+            variant: self.board.variant,
+        }
+    }
+
+    /// Notify every websocket subscriber of the current board.
+    /// Sending only fails when nobody is listening, which is fine.
+    fn publish(&self) {
+        let _ = self.updates.send(self.update());
+    }
+
+    /// End the game if the team to move has used up its time
+    async fn check_clock(&mut self) {
+        let team = self.board.next_team();
+        let ongoing = self.state() == GameState::Ongoing;
+        let Some(clock) = self.clock.as_mut() else {
+            return;
+        };
+        if !ongoing || clock.remaining(team, Utc::now()) >= chrono::Duration::zero() {
+            return;
+        }
+        info!("{team} ran out of time in game {}", self.game);
+        clock.flagged = Some(team);
+        if let Err(e) = self
+            .store
+            .finish(self.game, GameStatus::Finished, Some(self.state()))
+            .await
+        {
+            error!("Failed to store result of game {}: {e}", self.game);
+        }
+        self.publish();
+    }
+
+    /// Whether the game may be reset to `variant` and `time_control`. Anyone can start
+    /// the same game over once it's finished, but abandoning a game in progress or
+    /// changing the rules takes an admin.
+    fn may_reset(&self, admin: bool, variant: Variant, time_control: Option<TimeControl>) -> bool {
+        let same_rules =
+            variant == self.board.variant && time_control == self.clock.map(|clock| clock.control);
+        admin || (same_rules && (self.moves.is_empty() || self.state() != GameState::Ongoing))
+    }
+
+    /// Start over with an empty board under the given rules and time control
+    async fn reset(&mut self, variant: Variant, time_control: Option<TimeControl>) {
+        // A reset before any move keeps the same game rather than abandoning an empty one
+        let new_game = !self.moves.is_empty()
+            || variant != self.board.variant
+            || time_control != self.clock.map(|clock| clock.control);
+        if new_game && self.state() == GameState::Ongoing {
+            if let Err(e) = self
+                .store
+                .finish(self.game, GameStatus::Abandoned, None)
+                .await
+            {
+                error!("Failed to abandon game {}: {e}", self.game);
+            }
+        }
+        self.board.variant = variant;
+        self.clock = time_control.map(|control| Clock::new(control, Utc::now()));
+        self.clock_changed.notify_one();
+        if new_game {
+            self.start_game().await;
+        }
+        self.board.reset();
+        self.rng = rand::SeedableRng::seed_from_u64(2024);
+        self.publish();
+    }
+
+    /// Play a move with its 1-indexed column, recording it if it was allowed.
+    /// Timed games also require the teams to take turns.
+    async fn play(&mut self, played: Move) -> Result<(), Error> {
+        if !(1..=BOARD_SIZE).contains(&played.column) {
+            return Err(Error::InvalidColumn(played.column));
+        }
+        self.check_clock().await;
+        if self.state() != GameState::Ongoing {
+            return Err(Error::GameOver);
+        }
+        if self.clock.is_some() && played.team != self.board.next_team() {
+            return Err(Error::NotYourTurn(self.board.next_team()));
+        }
+        if let Err(e) = self.board.apply(&played) {
+            info!("{e}");
+            return Err(e);
+        }
+        if let Some(clock) = self.clock.as_mut() {
+            clock.record_move(played.team, played.played_at);
+            self.clock_changed.notify_one();
+        }
+        self.publish();
+
+        self.moves.push(played);
+        if let Err(e) = self
+            .store
+            .record_move(self.game, self.moves.len(), &played)
+            .await
+        {
+            error!("Failed to store move {played:?} of game {}: {e}", self.game);
+        }
+        let result = self.board.game_over();
+        if result != GameState::Ongoing {
+            if let Err(e) = self
+                .store
+                .finish(self.game, GameStatus::Finished, Some(result))
+                .await
+            {
+                error!("Failed to store result of game {}: {e}", self.game);
+            }
+        }
+        Ok(())
+    }
+
+    /// Turn the outcome of a move into the board response used by `place` and `pop`
+    fn move_response(&self, result: Result<(), Error>, htmx: bool) -> Response<String> {
+        let response_code = match result {
+            Ok(_) => StatusCode::OK,
+            Err(Error::InvalidColumn(_)) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("Invalid column number".into())
+                    .unwrap();
+            }
+            Err(e @ Error::PopOutDisabled) => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(e.to_string())
+                    .unwrap();
+            }
+            Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        print!("{}", self.render());
+
+        self.board_response(response_code, htmx)
+    }
+
+    fn board_response(&self, status: StatusCode, htmx: bool) -> Response<String> {
+        match htmx {
+            true => Response::builder()
+                .status(status)
+                .header(CONTENT_TYPE, "text/html")
+                .body(
+                    self.board
+                        .to_html(self.state(), self.clock.map(|clock| clock.control)),
+                )
+                .unwrap(),
+            false => Response::builder()
+                .status(status)
+                .body(self.render())
+                .unwrap(),
+        }
     }
 }
 
-pub(super) async fn board_state(State(state): State<Arc<Mutex<AppState>>>) -> String {
-    state.lock().unwrap().board.to_string()
+#[derive(Debug, Deserialize)]
+pub(super) struct GamesQuery {
+    status: Option<GameStatus>,
 }
 
-pub(super) async fn reset_board(State(state): State<Arc<Mutex<AppState>>>) -> String {
-    let mut state = state.lock().unwrap();
-    state.board.reset();
-    state.rng = rand::SeedableRng::seed_from_u64(2024);
-    state.board.to_string()
+#[derive(Debug, Deserialize)]
+pub(super) struct WatchQuery {
+    /// The team to play as; connections without one only watch
+    team: Option<SquareState>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ResetQuery {
+    #[serde(default)]
+    variant: Variant,
+    /// Optional clock for the new game, with its limit in `seconds`
+    clock: Option<ClockKind>,
+    seconds: Option<u32>,
+}
+
+/// Combine the clock parameters of a reset into a time control, if they make sense together
+fn time_control(
+    clock: Option<ClockKind>,
+    seconds: Option<u32>,
+) -> Result<Option<TimeControl>, &'static str> {
+    match (clock, seconds) {
+        (None, None) => Ok(None),
+        (Some(kind), Some(seconds)) if seconds > 0 => Ok(Some(TimeControl { kind, seconds })),
+        _ => Err("A clock needs both a kind and a positive number of seconds"),
+    }
+}
+
+pub(super) async fn play_page() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/html")
+        .body(PLAY_PAGE.into())
+        .unwrap()
+}
+
+pub(super) async fn board_state(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> Response<String> {
+    let mut state = state.lock().await;
+    state.check_clock().await;
+    state.board_response(StatusCode::OK, is_htmx(&headers))
+}
+
+pub(super) async fn reset_board(
+    State(state): State<Arc<Mutex<AppState>>>,
+    principal: Option<Principal>,
+    Query(query): Query<ResetQuery>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    let time_control = match time_control(query.clock, query.seconds) {
+        Ok(time_control) => time_control,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(e.into())
+                .unwrap();
+        }
+    };
+    let mut state = state.lock().await;
+    state.check_clock().await;
+    let admin = principal
+        .as_ref()
+        .is_some_and(|principal| principal.has_role(ADMIN_ROLE));
+    if !state.may_reset(admin, query.variant, time_control) {
+        return match principal {
+            Some(_) => AuthError::Forbidden(ADMIN_ROLE).into_response(),
+            None => AuthError::Missing.into_response(),
+        };
+    }
+    state.reset(query.variant, time_control).await;
+    state
+        .board_response(StatusCode::OK, is_htmx(&headers))
+        .into_response()
 }
 
 pub(super) async fn place(
     State(state): State<Arc<Mutex<AppState>>>,
-    Path((team, mut column)): Path<(SquareState, usize)>,
+    Path((team, column)): Path<(SquareState, usize)>,
+    headers: HeaderMap,
 ) -> Response<String> {
     info!("Placed {:?} in column {}", team, column);
 
-    if column >= 1 && column <= 4 {
-        column -= 1;
-    } else {
+    let mut state = state.lock().await;
+    let result = state.play(Move::new(team, column, MoveKind::Drop)).await;
+    state.move_response(result, is_htmx(&headers))
+}
+
+pub(super) async fn pop(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Path((team, column)): Path<(SquareState, usize)>,
+    headers: HeaderMap,
+) -> Response<String> {
+    info!("Popping {:?} from column {}", team, column);
+
+    let mut state = state.lock().await;
+    let result = state.play(Move::new(team, column, MoveKind::Pop)).await;
+    state.move_response(result, is_htmx(&headers))
+}
+
+pub(super) async fn random_board(State(state): State<Arc<Mutex<AppState>>>) -> String {
+    let mut state = state.lock().await;
+    let board = Board::random(&mut state.rng);
+    info!("Random board:\n{board}");
+    board.to_string()
+}
+
+pub(super) async fn watch(
+    State(state): State<Arc<Mutex<AppState>>>,
+    principal: Option<Principal>,
+    Query(query): Query<WatchQuery>,
+    ws: WebSocketUpgrade,
+) -> Response<Body> {
+    if query.team == Some(SquareState::Empty) {
         return Response::builder()
             .status(StatusCode::BAD_REQUEST)
-            .body("Invalid column number".into())
+            .body("Play as cookie or milk".into())
             .unwrap();
     }
-    let mut state = state.lock().unwrap();
-    let result = state.board.game_over();
-    if result != GameState::Ongoing {
-        return Response::builder()
-            .status(StatusCode::SERVICE_UNAVAILABLE)
-            .body(state.board.to_string().into())
-            .unwrap();
+    let admin = principal.is_some_and(|principal| principal.has_role(ADMIN_ROLE));
+    ws.on_upgrade(move |socket| play_over_socket(socket, state, admin, query.team))
+}
+
+/// Seat the client as `team` if nobody else is playing it, then relay moves and
+/// updates until it leaves. Clients without a seat watch without moving,
+/// and only admins may reset a game in progress.
+async fn play_over_socket(
+    mut socket: WebSocket,
+    state: Arc<Mutex<AppState>>,
+    admin: bool,
+    team: Option<SquareState>,
+) {
+    let (updates, current, seat) = {
+        let mut state = state.lock().await;
+        let seat = team.filter(|team| !state.seats.contains(team));
+        state.seats.extend(seat);
+        (state.updates.subscribe(), state.update(), seat)
+    };
+    if let Some(team) = team.filter(|_| seat.is_none()) {
+        let taken = format!(
+            "Someone is already playing {}, so you're watching",
+            team.as_str()
+        );
+        let error = serde_json::json!({ "error": taken }).to_string();
+        let _ = socket.send(Message::Text(error)).await;
+    }
+    if send_update(&mut socket, &current).await.is_ok() {
+        relay(socket, &state, updates, admin, seat).await;
     }
-    let response_code: StatusCode;
-    match state.board.place_item(column, team) {
-        Ok(_) => {
-            response_code = StatusCode::OK;
+    if let Some(seat) = seat {
+        state.lock().await.seats.retain(|team| *team != seat);
+    }
+}
+
+/// Whether the client in `seat` may move for `team`
+fn check_seat(seat: Option<SquareState>, team: SquareState) -> Result<(), String> {
+    match seat {
+        None => {
+            Err("Spectators can't move; connect with ?team=cookie or ?team=milk to play".into())
         }
-        Err(_) => response_code = StatusCode::SERVICE_UNAVAILABLE,
-    };
+        Some(seat) if seat != team => Err(format!("This connection plays {}", seat.as_str())),
+        Some(_) => Ok(()),
+    }
+}
 
-    print!("{}", state.board.to_string());
+async fn relay(
+    mut socket: WebSocket,
+    state: &Mutex<AppState>,
+    mut updates: broadcast::Receiver<BoardUpdate>,
+    admin: bool,
+    seat: Option<SquareState>,
+) {
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    if send_update(&mut socket, &update).await.is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    info!("Websocket subscriber skipped {skipped} updates");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let played = match serde_json::from_str::<Command>(&text) {
+                        Ok(Command::Place { team, column }) => {
+                            check_seat(seat, team).map(|_| Some(Move::new(team, column, MoveKind::Drop)))
+                        }
+                        Ok(Command::Pop { team, column }) => {
+                            check_seat(seat, team).map(|_| Some(Move::new(team, column, MoveKind::Pop)))
+                        }
+                        Ok(Command::Reset { variant, clock, seconds }) => match time_control(clock, seconds) {
+                            Ok(time_control) => {
+                                let mut state = state.lock().await;
+                                state.check_clock().await;
+                                match state.may_reset(admin, variant, time_control) {
+                                    true => {
+                                        state.reset(variant, time_control).await;
+                                        Ok(None)
+                                    }
+                                    false => Err(format!("Resetting a game in progress or changing its rules requires the {ADMIN_ROLE} role")),
+                                }
+                            }
+                            Err(e) => Err(e.to_string()),
+                        },
+                        Err(e) => Err(format!("Invalid command: {e}")),
+                    };
+                    let rejection = match played {
+                        Ok(Some(played)) => state.lock().await.play(played).await.err().map(|e| e.to_string()),
+                        Ok(None) => None,
+                        Err(rejection) => Some(rejection),
+                    };
+                    if let Some(rejection) = rejection {
+                        let error = serde_json::json!({ "error": rejection }).to_string();
+                        if socket.send(Message::Text(error)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => return,
+                Some(Ok(_)) => (),
+                Some(Err(e)) => {
+                    info!("Websocket error: {e}");
+                    return;
+                }
+            },
+        }
+    }
+}
 
-    Response::builder()
-        .status(response_code)
-        .body(state.board.to_string().into())
-        .unwrap()
+async fn send_update(socket: &mut WebSocket, update: &BoardUpdate) -> Result<(), axum::Error> {
+    let update = serde_json::to_string(update).unwrap();
+    socket.send(Message::Text(update)).await
 }
 
-pub(super) async fn random_board(State(state): State<Arc<Mutex<AppState>>>) -> String {
-    let mut state = state.lock().unwrap();
-    let board = Board::random(&mut state.rng);
-    info!("Random board:\n{board}");
-    board.to_string()
+pub(super) async fn games(
+    State(state): State<Arc<Mutex<AppState>>>,
+    Query(query): Query<GamesQuery>,
+) -> Response<Body> {
+    let store = state.lock().await.store.clone();
+    match store.list(query.status).await {
+        Ok(games) => Json(games).into_response(),
+        Err(e) => {
+            error!("Failed to list games: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("".into())
+                .unwrap()
+        }
+    }
 }
@@ -0,0 +1,89 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::SquareState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ClockKind {
+    /// Each move must be made within the limit
+    Move,
+    /// Each team has the limit to spend across the whole game
+    Total,
+}
+
+impl ClockKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ClockKind::Move => "move",
+            ClockKind::Total => "total",
+        }
+    }
+
+    pub(crate) fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "move" => Some(ClockKind::Move),
+            "total" => Some(ClockKind::Total),
+            _ => None,
+        }
+    }
+}
+
+/// The time limit a game is created with, applying equally to both teams
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub(crate) struct TimeControl {
+    pub(crate) kind: ClockKind,
+    pub(crate) seconds: u32,
+}
+
+/// Tracks how long each team has spent thinking in a timed game
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Clock {
+    pub(crate) control: TimeControl,
+    /// Time used by cookie and milk respectively
+    used: [Duration; 2],
+    /// When the team to move started its turn
+    turn_started: DateTime<Utc>,
+    /// The team whose flag fell, losing the game on time
+    pub(crate) flagged: Option<SquareState>,
+}
+
+impl Clock {
+    pub(crate) fn new(control: TimeControl, started: DateTime<Utc>) -> Self {
+        Self {
+            control,
+            used: [Duration::zero(); 2],
+            turn_started: started,
+            flagged: None,
+        }
+    }
+
+    fn index(team: SquareState) -> usize {
+        match team {
+            SquareState::Milk => 1,
+            _ => 0,
+        }
+    }
+
+    /// Time `team` has left at `now`, assuming it's the team to move
+    pub(crate) fn remaining(&self, team: SquareState, now: DateTime<Utc>) -> Duration {
+        let limit = Duration::seconds(self.control.seconds.into());
+        let thinking = now - self.turn_started;
+        match self.control.kind {
+            ClockKind::Move => limit - thinking,
+            ClockKind::Total => limit - self.used[Self::index(team)] - thinking,
+        }
+    }
+
+    /// Charge `team` for the turn it just finished at `now` and start the next turn
+    pub(crate) fn record_move(&mut self, team: SquareState, now: DateTime<Utc>) {
+        self.used[Self::index(team)] += now - self.turn_started;
+        self.turn_started = now;
+    }
+
+    /// Restart the turn in progress at `now`, so time the server spent down
+    /// isn't charged to the team to move
+    pub(crate) fn resume(&mut self, now: DateTime<Utc>) {
+        self.turn_started = now;
+    }
+}
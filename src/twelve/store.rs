@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use super::{
+    clock::{ClockKind, TimeControl},
+    GameState, Move, MoveKind, SquareState, Variant,
+};
+
+/// Lifecycle of a stored game
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum GameStatus {
+    Ongoing,
+    Finished,
+    Abandoned,
+}
+
+impl GameStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GameStatus::Ongoing => "ongoing",
+            GameStatus::Finished => "finished",
+            GameStatus::Abandoned => "abandoned",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "ongoing" => Some(GameStatus::Ongoing),
+            "finished" => Some(GameStatus::Finished),
+            "abandoned" => Some(GameStatus::Abandoned),
+            _ => None,
+        }
+    }
+}
+
+/// A stored value this version doesn't recognise
+#[derive(Debug, thiserror::Error)]
+#[error("Unrecognised value {0:?}")]
+struct UnknownValue(String);
+
+/// Read a text column with `parse`, failing rather than guessing when the value is unknown
+fn decode<T>(column: &str, value: &str, parse: fn(&str) -> Option<T>) -> Result<T, sqlx::Error> {
+    parse(value).ok_or_else(|| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: Box::new(UnknownValue(value.to_string())),
+    })
+}
+
+#[derive(Debug, FromRow)]
+struct GameRow {
+    id: Uuid,
+    variant: String,
+    width: i32,
+    height: i32,
+    status: String,
+    result: Option<String>,
+    clock: Option<String>,
+    clock_seconds: Option<i32>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, FromRow)]
+struct MoveRow {
+    game_id: Uuid,
+    team: String,
+    column_number: i32,
+    kind: String,
+    played_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl TryFrom<MoveRow> for Move {
+    type Error = sqlx::Error;
+
+    fn try_from(row: MoveRow) -> Result<Self, Self::Error> {
+        Ok(Move {
+            team: decode("team", &row.team, SquareState::parse)?,
+            column: row.column_number as usize,
+            kind: decode("kind", &row.kind, MoveKind::parse)?,
+            played_at: row.played_at,
+        })
+    }
+}
+
+/// A game as reported by `/12/games`
+#[derive(Debug, Serialize)]
+pub(crate) struct GameRecord {
+    id: Uuid,
+    variant: Variant,
+    time_control: Option<TimeControl>,
+    width: i32,
+    height: i32,
+    status: GameStatus,
+    result: Option<GameState>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    moves: Vec<Move>,
+}
+
+/// An unfinished game loaded from the store, with everything needed to resume it
+pub(crate) struct StoredGame {
+    pub(crate) id: Uuid,
+    pub(crate) variant: Variant,
+    pub(crate) time_control: Option<TimeControl>,
+    pub(crate) created_at: chrono::DateTime<chrono::Utc>,
+    pub(crate) moves: Vec<Move>,
+}
+
+fn time_control(
+    clock: Option<&str>,
+    seconds: Option<i32>,
+) -> Result<Option<TimeControl>, sqlx::Error> {
+    let (Some(clock), Some(seconds)) = (clock, seconds) else {
+        return Ok(None);
+    };
+    Ok(Some(TimeControl {
+        kind: decode("clock", clock, ClockKind::parse)?,
+        seconds: u32::try_from(seconds).map_err(|e| sqlx::Error::ColumnDecode {
+            index: "clock_seconds".to_string(),
+            source: Box::new(e),
+        })?,
+    }))
+}
+
+/// Persists day 12 games and their moves so they survive a restart
+#[derive(Clone, Debug)]
+pub(crate) struct GameStore {
+    pool: PgPool,
+}
+
+impl GameStore {
+    pub(crate) fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub(crate) async fn start(
+        &self,
+        id: Uuid,
+        variant: Variant,
+        time_control: Option<TimeControl>,
+        width: usize,
+        height: usize,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO games (id, variant, clock, clock_seconds, width, height) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(id)
+        .bind(variant.as_str())
+        .bind(time_control.map(|control| control.kind.as_str()))
+        .bind(time_control.map(|control| control.seconds as i32))
+        .bind(width as i32)
+        .bind(height as i32)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record the `number`th move (counting from 1) of a game
+    pub(crate) async fn record_move(
+        &self,
+        id: Uuid,
+        number: usize,
+        played: &Move,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO game_moves (game_id, number, team, column_number, kind, played_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(id)
+        .bind(number as i32)
+        .bind(played.team.as_str())
+        .bind(played.column as i32)
+        .bind(played.kind.as_str())
+        .bind(played.played_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn finish(
+        &self,
+        id: Uuid,
+        status: GameStatus,
+        result: Option<GameState>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE games SET status = $1, result = $2, finished_at = CURRENT_TIMESTAMP WHERE id = $3",
+        )
+        .bind(status.as_str())
+        .bind(result.map(|result| result.as_str()))
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The most recently started game that hasn't finished, along with its moves in order
+    pub(crate) async fn latest_ongoing(&self) -> Result<Option<StoredGame>, sqlx::Error> {
+        let game = sqlx::query_as::<_, GameRow>(
+            "SELECT * FROM games WHERE status = 'ongoing' ORDER BY created_at DESC LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(game) = game else {
+            return Ok(None);
+        };
+        let moves = sqlx::query_as::<_, MoveRow>(
+            "SELECT game_id, team, column_number, kind, played_at FROM game_moves WHERE game_id = $1 ORDER BY number",
+        )
+        .bind(game.id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(Some(StoredGame {
+            id: game.id,
+            variant: decode("variant", &game.variant, Variant::parse)?,
+            time_control: time_control(game.clock.as_deref(), game.clock_seconds)?,
+            created_at: game.created_at,
+            moves: moves
+                .into_iter()
+                .map(Move::try_from)
+                .collect::<Result<_, _>>()?,
+        }))
+    }
+
+    pub(crate) async fn list(
+        &self,
+        status: Option<GameStatus>,
+    ) -> Result<Vec<GameRecord>, sqlx::Error> {
+        let games = sqlx::query_as::<_, GameRow>(
+            "SELECT * FROM games WHERE $1::TEXT IS NULL OR status = $1 ORDER BY created_at",
+        )
+        .bind(status.map(|status| status.as_str()))
+        .fetch_all(&self.pool)
+        .await?;
+        let ids: Vec<Uuid> = games.iter().map(|game| game.id).collect();
+        let moves = sqlx::query_as::<_, MoveRow>(
+            "SELECT game_id, team, column_number, kind, played_at FROM game_moves WHERE game_id = ANY($1) ORDER BY game_id, number",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut moves_by_game: HashMap<Uuid, Vec<Move>> = HashMap::new();
+        for row in moves {
+            moves_by_game
+                .entry(row.game_id)
+                .or_default()
+                .push(row.try_into()?);
+        }
+
+        games
+            .into_iter()
+            .map(|game| {
+                Ok(GameRecord {
+                    moves: moves_by_game.remove(&game.id).unwrap_or_default(),
+                    id: game.id,
+                    variant: decode("variant", &game.variant, Variant::parse)?,
+                    time_control: time_control(game.clock.as_deref(), game.clock_seconds)?,
+                    width: game.width,
+                    height: game.height,
+                    status: decode("status", &game.status, GameStatus::parse)?,
+                    result: game
+                        .result
+                        .as_deref()
+                        .map(|result| decode("result", result, GameState::parse))
+                        .transpose()?,
+                    created_at: game.created_at,
+                    finished_at: game.finished_at,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,390 @@
+use axum::{
+    body::Body,
+    extract::Json,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use tracing::{error, info};
+
+use super::{Board, GameState, SquareState, BOARD_SIZE};
+
+/// Upper bounds that keep a single request from tying up the server.
+/// Minimax looks at up to `BOARD_SIZE^depth` positions per move, so its depth stays small,
+/// and the game limit covers the whole round-robin rather than each pairing.
+const MAX_STRATEGIES: usize = 8;
+const MAX_DEPTH: usize = 6;
+const MAX_GAMES: usize = 1000;
+
+const INITIAL_ELO: f64 = 1500.0;
+const ELO_K_FACTOR: f64 = 32.0;
+
+/// Score of a won position, large enough to dominate any heuristic evaluation
+const WIN_SCORE: i32 = 1_000_000;
+
+/// A way of picking moves, pitted against other strategies in a tournament
+pub(crate) trait Strategy: Send {
+    fn name(&self) -> String;
+
+    /// Pick the 0-indexed column to drop `team` into.
+    /// Only called while at least one column still has room.
+    fn choose(&self, board: &Board, team: SquareState, rng: &mut StdRng) -> usize;
+}
+
+/// Plays any open column
+struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+
+    fn choose(&self, board: &Board, _team: SquareState, rng: &mut StdRng) -> usize {
+        *board.open_columns().choose(rng).unwrap()
+    }
+}
+
+/// Takes a win when one is available, otherwise blocks the opponent's, otherwise plays randomly
+struct GreedyStrategy;
+
+impl Strategy for GreedyStrategy {
+    fn name(&self) -> String {
+        "greedy".to_string()
+    }
+
+    fn choose(&self, board: &Board, team: SquareState, rng: &mut StdRng) -> usize {
+        let open = board.open_columns();
+        for player in [team, team.opponent()] {
+            for &column in &open {
+                let mut next = board.clone();
+                next.drop_item(column, player).unwrap();
+                if next.game_over().winner() == Some(player) {
+                    return column;
+                }
+            }
+        }
+        *open.choose(rng).unwrap()
+    }
+}
+
+/// Searches `depth` moves ahead with alpha-beta pruning, scoring the leaves by open lines
+struct MinimaxStrategy {
+    depth: usize,
+}
+
+impl MinimaxStrategy {
+    fn negamax(
+        &self,
+        board: &Board,
+        team: SquareState,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        match board.game_over() {
+            GameState::Ongoing => (),
+            GameState::Draw => return 0,
+            // Prefer quicker wins and slower losses
+            result if result.winner() == Some(team) => return WIN_SCORE + depth as i32,
+            _ => return -WIN_SCORE - depth as i32,
+        }
+        if depth == 0 {
+            return evaluate(board, team);
+        }
+        let mut best = i32::MIN + 1;
+        for column in board.open_columns() {
+            let mut next = board.clone();
+            next.drop_item(column, team).unwrap();
+            let score = -self.negamax(&next, team.opponent(), depth - 1, -beta, -alpha);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+impl Strategy for MinimaxStrategy {
+    fn name(&self) -> String {
+        format!("minimax:{}", self.depth)
+    }
+
+    fn choose(&self, board: &Board, team: SquareState, rng: &mut StdRng) -> usize {
+        let mut best_score = i32::MIN;
+        let mut best_columns = Vec::new();
+        for column in board.open_columns() {
+            let mut next = board.clone();
+            next.drop_item(column, team).unwrap();
+            let score = -self.negamax(
+                &next,
+                team.opponent(),
+                self.depth.saturating_sub(1),
+                i32::MIN + 1,
+                i32::MAX,
+            );
+            if score > best_score {
+                best_score = score;
+                best_columns.clear();
+            }
+            if score == best_score {
+                best_columns.push(column);
+            }
+        }
+        // Break ties randomly so seeded tournaments don't replay the same game over and over
+        *best_columns.choose(rng).unwrap()
+    }
+}
+
+/// Heuristic value of a position for `team`: lines only one side can still complete,
+/// weighted by how many of its items are already in them
+fn evaluate(board: &Board, team: SquareState) -> i32 {
+    const WEIGHTS: [i32; 4] = [0, 1, 4, 16];
+    let mut score = 0;
+    for line in LINES.iter() {
+        let ours = line
+            .iter()
+            .filter(|&&(row, column)| *board.cell(row, column) == team)
+            .count();
+        let theirs = line
+            .iter()
+            .filter(|&&(row, column)| *board.cell(row, column) == team.opponent())
+            .count();
+        match (ours, theirs) {
+            (ours, 0) => score += WEIGHTS[ours.min(3)],
+            (0, theirs) => score -= WEIGHTS[theirs.min(3)],
+            _ => (),
+        }
+    }
+    score
+}
+
+/// Every run of four squares that wins the game when filled by one team, as (row, column) pairs
+static LINES: LazyLock<Vec<[(usize, usize); 4]>> = LazyLock::new(|| {
+    let mut lines = Vec::new();
+    for row in 0..BOARD_SIZE {
+        for column in 0..BOARD_SIZE {
+            for (row_step, column_step) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+                let end_row = row as isize + 3 * row_step;
+                let end_column = column as isize + 3 * column_step;
+                if end_row >= BOARD_SIZE as isize
+                    || end_column < 0
+                    || end_column >= BOARD_SIZE as isize
+                {
+                    continue;
+                }
+                lines.push(std::array::from_fn(|i| {
+                    (
+                        (row as isize + i as isize * row_step) as usize,
+                        (column as isize + i as isize * column_step) as usize,
+                    )
+                }));
+            }
+        }
+    }
+    lines
+});
+
+/// Build a strategy from its name, with minimax taking its depth as `minimax:<depth>`
+fn parse_strategy(spec: &str) -> Option<Box<dyn Strategy>> {
+    match spec.split_once(':') {
+        None if spec == "random" => Some(Box::new(RandomStrategy)),
+        None if spec == "greedy" => Some(Box::new(GreedyStrategy)),
+        None if spec == "minimax" => Some(Box::new(MinimaxStrategy { depth: 4 })),
+        Some(("minimax", depth)) => match depth.parse() {
+            Ok(depth) if (1..=MAX_DEPTH).contains(&depth) => {
+                Some(Box::new(MinimaxStrategy { depth }))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Play a single game to the end, with cookie moving first
+fn play(cookie: &dyn Strategy, milk: &dyn Strategy, rng: &mut StdRng) -> GameState {
+    let mut board = Board::new();
+    let mut team = SquareState::Cookie;
+    loop {
+        let result = board.game_over();
+        if result != GameState::Ongoing {
+            return result;
+        }
+        let player = match team {
+            SquareState::Cookie => cookie,
+            _ => milk,
+        };
+        let column = player.choose(&board, team, rng);
+        if board.drop_item(column, team).is_err() {
+            // An illegal move forfeits the game
+            return match team.opponent() {
+                SquareState::Cookie => GameState::Cookie,
+                _ => GameState::Milk,
+            };
+        }
+        team = team.opponent();
+    }
+}
+
+fn default_strategies() -> Vec<String> {
+    vec![
+        "random".to_string(),
+        "greedy".to_string(),
+        "minimax:4".to_string(),
+    ]
+}
+
+fn default_games() -> usize {
+    10
+}
+
+fn default_seed() -> u64 {
+    2024
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TournamentRequest {
+    #[serde(default = "default_strategies")]
+    strategies: Vec<String>,
+    /// Games played by each pairing, alternating who moves first
+    #[serde(default = "default_games")]
+    games: usize,
+    #[serde(default = "default_seed")]
+    seed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Standing {
+    strategy: String,
+    played: usize,
+    wins: usize,
+    draws: usize,
+    losses: usize,
+    elo: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TournamentResults {
+    games_per_pairing: usize,
+    seed: u64,
+    standings: Vec<Standing>,
+}
+
+/// Expected score of a player rated `rating` against one rated `opponent`
+fn expected_score(rating: f64, opponent: f64) -> f64 {
+    1.0 / (1.0 + 10_f64.powf((opponent - rating) / 400.0))
+}
+
+/// Run a round-robin where every pair of strategies plays `games` games against each other
+fn run(strategies: &[Box<dyn Strategy>], games: usize, seed: u64) -> Vec<Standing> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut standings: Vec<Standing> = strategies
+        .iter()
+        .map(|strategy| Standing {
+            strategy: strategy.name(),
+            played: 0,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+            elo: INITIAL_ELO,
+        })
+        .collect();
+
+    for first in 0..strategies.len() {
+        for second in first + 1..strategies.len() {
+            for game in 0..games {
+                let (cookie, milk) = match game % 2 {
+                    0 => (first, second),
+                    _ => (second, first),
+                };
+                let result = play(
+                    strategies[cookie].as_ref(),
+                    strategies[milk].as_ref(),
+                    &mut rng,
+                );
+                // Score from the cookie player's point of view
+                let score = match result {
+                    GameState::Cookie => 1.0,
+                    GameState::Milk => 0.0,
+                    _ => 0.5,
+                };
+                let expected = expected_score(standings[cookie].elo, standings[milk].elo);
+                standings[cookie].elo += ELO_K_FACTOR * (score - expected);
+                standings[milk].elo -= ELO_K_FACTOR * (score - expected);
+
+                for (player, score) in [(cookie, score), (milk, 1.0 - score)] {
+                    let standing = &mut standings[player];
+                    standing.played += 1;
+                    match score {
+                        1.0 => standing.wins += 1,
+                        0.0 => standing.losses += 1,
+                        _ => standing.draws += 1,
+                    }
+                }
+            }
+        }
+    }
+
+    for standing in standings.iter_mut() {
+        standing.elo = standing.elo.round();
+    }
+    standings.sort_by(|a, b| b.elo.total_cmp(&a.elo));
+    standings
+}
+
+pub(crate) async fn tournament(Json(request): Json<TournamentRequest>) -> Response<Body> {
+    info!("Running tournament: {:?}", request);
+    let pairings = request.strategies.len() * request.strategies.len().saturating_sub(1) / 2;
+    if !(2..=MAX_STRATEGIES).contains(&request.strategies.len())
+        || request.games == 0
+        || request.games.saturating_mul(pairings) > MAX_GAMES
+    {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(
+                format!(
+                    "A tournament needs 2 to {MAX_STRATEGIES} strategies and at least one game per pairing, with no more than {MAX_GAMES} games in total"
+                )
+                .into(),
+            )
+            .unwrap();
+    }
+    let mut strategies = Vec::new();
+    for spec in &request.strategies {
+        match parse_strategy(spec) {
+            Some(strategy) => strategies.push(strategy),
+            None => {
+                return Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(
+                        format!(
+                            "Unknown strategy: {spec} (minimax takes a depth of 1 to {MAX_DEPTH})"
+                        )
+                        .into(),
+                    )
+                    .unwrap();
+            }
+        }
+    }
+
+    let (games, seed) = (request.games, request.seed);
+    let standings = tokio::task::spawn_blocking(move || run(&strategies, games, seed)).await;
+    match standings {
+        Ok(standings) => Json(TournamentResults {
+            games_per_pairing: games,
+            seed,
+            standings,
+        })
+        .into_response(),
+        Err(e) => {
+            error!("Tournament failed: {e}");
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("".into())
+                .unwrap()
+        }
+    }
+}
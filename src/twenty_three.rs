@@ -110,16 +110,16 @@ pub(super) async fn lockfile(mut multipart: Multipart) -> Response<Body> {
     let mut lock_file = None;
 
     while let Some(field) = multipart.next_field().await.unwrap_or_default() {
-        if field.name().unwrap().to_string() == "lockfile" {
+        if field.name().unwrap() == "lockfile" {
             let bytes = field.bytes().await.unwrap();
             let lockfile_str = str::from_utf8(&bytes).unwrap();
             let parse_result: Result<Lockfile, toml::de::Error> = toml::from_str(lockfile_str);
-            if parse_result.is_err() {
-                let error = parse_result.err().unwrap();
-                info!("{error}");
-                return Response::builder().status(400).body("".into()).unwrap();
-            } else {
-                lock_file = Some(parse_result.unwrap());
+            match parse_result {
+                Ok(parsed) => lock_file = Some(parsed),
+                Err(error) => {
+                    info!("{error}");
+                    return Response::builder().status(400).body("".into()).unwrap();
+                }
             }
         }
     }